@@ -0,0 +1,366 @@
+use serde::{Serialize, Deserialize};
+use sodalite::SecretboxKey;
+use zeroize::Zeroizing;
+
+use std::os::unix::net::{ UnixListener, UnixStream };
+use std::os::unix::fs::{ DirBuilderExt, PermissionsExt };
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::io::{ self, BufRead, BufReader, Write };
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+use std::time::{ Duration, SystemTime };
+use std::{ fs, thread };
+
+use crate::wallet::{ Address, WalletStore };
+use crate::crypto::*;
+use crate::error::Result;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Request {
+  /// Sign hex encoded payload by the seed of `addr`
+  Sign { addr: String, payload: String },
+  /// Encrypt hex encoded seed by the master key
+  Seal { seed: String },
+  Status,
+  Lock,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+  Signed { public: String, signature: String },
+  Sealed { seed: String },
+  Status { expires_at: u64 },
+  Locked,
+  Error { message: String },
+}
+
+/// Directory of agent sockets in data path, only the owner can access it
+const SOCKETS_DIR: &str = "agent";
+
+/// Each wallet has its own agent
+pub fn socket_path(data_path: &Path, wallet: &str) -> PathBuf {
+  data_path.join(SOCKETS_DIR).join(format!("{}.sock", wallet))
+}
+
+/// Uid of the process on the other end of `stream`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+  let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+  let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+  let ret = unsafe {
+    libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED, &mut cred as *mut _ as *mut libc::c_void, &mut len)
+  };
+  if ret != 0 {
+    return Err(io::Error::last_os_error())
+  }
+  Ok(cred.uid)
+}
+
+/// Uid of the process on the other end of `stream`
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+  let (mut uid, mut gid) = (0, 0);
+  if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+    return Err(io::Error::last_os_error())
+  }
+  Ok(uid)
+}
+
+/// Bind the socket in a directory which only the owner can access, the socket is never accessible to others,
+/// not even between `bind` and `chmod`
+fn bind(path: &Path) -> Result<UnixListener> {
+  if let Some(dir) = path.parent() {
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+  }
+  let _ = fs::remove_file(path);
+  let umask = unsafe { libc::umask(0o077) };
+  let listener = UnixListener::bind(path);
+  unsafe { libc::umask(umask) };
+  let listener = listener?;
+  fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+  Ok(listener)
+}
+
+fn now() -> u64 {
+  SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Client of the unlock agent
+pub struct Client {
+  path: PathBuf,
+}
+
+impl Client {
+  /// Returns `None` if the agent is not running
+  pub fn connect(data_path: &Path, wallet: &str) -> Option<Self> {
+    let client = Self { path: socket_path(data_path, wallet) };
+    match client.request(&Request::Status) {
+      Ok(Response::Status { .. }) => Some(client),
+      _ => None,
+    }
+  }
+
+  fn request(&self, request: &Request) -> Result<Response> {
+    let mut stream = UnixStream::connect(&self.path)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut data = serde_json::to_string(request)?;
+    data.push('\n');
+    stream.write_all(data.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    match serde_json::from_str::<Response>(&line)? {
+      Response::Error { message } => Err(message.into()),
+      response => Ok(response),
+    }
+  }
+
+  /// Returns public key and signature
+  pub fn sign(&self, addr: &str, payload: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let request = Request::Sign {
+      addr: addr.to_string(),
+      payload: hex::encode(payload),
+    };
+    match self.request(&request)? {
+      Response::Signed { public, signature } => {
+        let public = hex::decode(public).map_err(|_| "Invalid public key from agent")?;
+        let signature = hex::decode(signature).map_err(|_| "Invalid signature from agent")?;
+        Ok((public, signature))
+      },
+      _ => Err("Unexpected response from agent".into()),
+    }
+  }
+
+  pub fn seal(&self, seed: &[u8]) -> Result<Vec<u8>> {
+    let request = Request::Seal { seed: hex::encode(seed) };
+    match self.request(&request)? {
+      Response::Sealed { seed } => Ok(hex::decode(seed).map_err(|_| "Invalid seed from agent")?),
+      _ => Err("Unexpected response from agent".into()),
+    }
+  }
+
+  pub fn expires_at(&self) -> Result<u64> {
+    match self.request(&Request::Status)? {
+      Response::Status { expires_at } => Ok(expires_at),
+      _ => Err("Unexpected response from agent".into()),
+    }
+  }
+
+  pub fn lock(&self) -> Result<()> {
+    match self.request(&Request::Lock)? {
+      Response::Locked => Ok(()),
+      _ => Err("Unexpected response from agent".into()),
+    }
+  }
+}
+
+/// Start the agent in a new session, the master key is sent through stdin
+pub fn spawn(data_path: &Path, wallet: &str, key: &SecretboxKey, timeout: u64) -> Result<()> {
+  let mut command = Command::new(std::env::current_exe()?);
+  command.arg("--datadir")
+    .arg(data_path)
+    .arg("--wallet")
+    .arg(wallet)
+    .arg("agent")
+    .arg(timeout.to_string())
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null());
+  // detach from terminal, so the agent is not killed when terminal is closed
+  unsafe {
+    command.pre_exec(|| {
+      libc::setsid();
+      Ok(())
+    });
+  }
+
+  let mut child = command.spawn()?;
+  if let Some(mut stdin) = child.stdin.take() {
+    let encoded = Zeroizing::new(hex::encode(&key[..]));
+    stdin.write_all(encoded.as_bytes())?;
+    stdin.write_all(b"\n")?;
+  }
+
+  for _ in 0..50 {
+    if Client::connect(data_path, wallet).is_some() {
+      return Ok(())
+    }
+    thread::sleep(Duration::from_millis(100));
+  }
+  Err("Failed to start unlock agent".into())
+}
+
+/// Read the master key from stdin, then serve requests until timeout or `walletlock`
+pub fn run(data_path: &Path, wallet: &str, timeout: u64) -> Result<()> {
+  let mut line = Zeroizing::new(String::new());
+  io::stdin().read_line(&mut line)?;
+  let decoded = Zeroizing::new(hex::decode(line.trim()).map_err(|_| "Invalid master key")?);
+  if decoded.len() != 32 {
+    return Err("Invalid master key".into())
+  }
+  let mut key = Zeroizing::new([0u8; 32]);
+  key.copy_from_slice(&decoded[..]);
+
+  let store = WalletStore::open(data_path.to_str(), wallet);
+  store.set_master_key(key);
+
+  let path = socket_path(data_path, wallet);
+  let listener = bind(&path)?;
+  listener.set_nonblocking(true)?;
+  let uid = unsafe { libc::getuid() };
+
+  let expires_at = now() + timeout;
+  while now() < expires_at {
+    match listener.accept() {
+      // the agent signs anything, so only processes of the same user are served
+      Ok((stream, _)) if peer_uid(&stream).ok() != Some(uid) => drop(stream),
+      Ok((stream, _)) => {
+        if let Ok(true) = handle(&store, stream, expires_at) {
+          break;
+        }
+      },
+      Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+        thread::sleep(Duration::from_millis(100));
+      },
+      Err(_) => break,
+    }
+  }
+
+  store.lock();
+  let _ = fs::remove_file(&path);
+  Ok(())
+}
+
+/// Returns true if the wallet is locked by request
+fn handle(store: &WalletStore, stream: UnixStream, expires_at: u64) -> Result<bool> {
+  stream.set_nonblocking(false)?;
+  stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+  let mut line = Zeroizing::new(String::new());
+  BufReader::new(&stream).read_line(&mut line)?;
+  let (response, locked) = match serde_json::from_str::<Request>(&line) {
+    Ok(Request::Sign { addr, payload }) => (sign(store, &addr, &payload), false),
+    Ok(Request::Seal { seed }) => {
+      let seed = Zeroizing::new(seed);
+      (seal(store, &seed), false)
+    },
+    Ok(Request::Status) => (Response::Status { expires_at }, false),
+    Ok(Request::Lock) => (Response::Locked, true),
+    Err(_) => (error("Invalid request"), false),
+  };
+
+  let mut data = serde_json::to_string(&response)?;
+  data.push('\n');
+  (&stream).write_all(data.as_bytes())?;
+  Ok(locked)
+}
+
+fn error(message: &str) -> Response {
+  Response::Error { message: message.to_string() }
+}
+
+fn sign(store: &WalletStore, addr: &str, payload: &str) -> Response {
+  let address = match store.read(addr) {
+    Some(address) if !address.is_watchonly() => address,
+    _ => return error("Address does not exist or is watchonly"),
+  };
+  let payload = match hex::decode(payload) {
+    Ok(payload) => payload,
+    Err(_) => return error("Invalid payload"),
+  };
+  let result = match address.crypto_type.as_str() {
+    "sr25519" => sign_with::<Sr25519>(&address, &payload[..]),
+    "ed25519" => sign_with::<Ed25519>(&address, &payload[..]),
+    "ecdsa" => sign_with::<Ecdsa>(&address, &payload[..]),
+    _ => Err(()),
+  };
+  match result {
+    Ok((public, signature)) => Response::Signed {
+      public: hex::encode(public),
+      signature: hex::encode(signature),
+    },
+    Err(_) => error("Failed to sign payload"),
+  }
+}
+
+fn sign_with<T: Crypto>(address: &Address, payload: &[u8]) -> std::result::Result<(Vec<u8>, Vec<u8>), ()> {
+  let pair = address.try_into_pair::<T>()?;
+  let signature = pair.sign(payload);
+  Ok((pair.public().to_raw_vec(), signature.as_ref().to_vec()))
+}
+
+fn seal(store: &WalletStore, seed: &str) -> Response {
+  let seed = match hex::decode(seed) {
+    Ok(seed) => Zeroizing::new(seed),
+    Err(_) => return error("Invalid seed"),
+  };
+  match store.seal(&seed[..]) {
+    Ok(sealed) => Response::Sealed { seed: hex::encode(sealed) },
+    Err(_) => error("Failed to encrypt seed"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_request_json() {
+    let request = Request::Sign { addr: "addr".to_string(), payload: "00".to_string() };
+    let json = serde_json::to_string(&request).unwrap();
+    assert_eq!(json, r#"{"method":"sign","addr":"addr","payload":"00"}"#);
+    assert_eq!(serde_json::from_str::<Request>(&json).unwrap(), request);
+
+    let json = serde_json::to_string(&Response::Locked).unwrap();
+    assert_eq!(json, r#"{"result":"locked"}"#);
+  }
+
+  #[test]
+  fn test_sign_and_seal() {
+    let path = std::env::temp_dir().join(format!("subwallet-agent-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    let store = WalletStore::init(path.to_str());
+    let address = Address::generate::<Sr25519>();
+    store.save(address.clone()).unwrap();
+    store.encrypt("111111").unwrap();
+
+    let agent_store = WalletStore::init(path.to_str());
+    agent_store.set_master_key(store.master_key().unwrap());
+    match sign(&agent_store, &address.addr, "0102") {
+      Response::Signed { public, signature } => {
+        let public = sr25519::Public::from_slice(&hex::decode(public).unwrap()[..]);
+        let signature = sr25519::Signature::from_slice(&hex::decode(signature).unwrap()[..]);
+        assert!(sr25519::Pair::verify(&signature, &[1u8, 2u8][..], &public));
+      },
+      _ => unreachable!(),
+    }
+
+    match seal(&agent_store, "0102") {
+      Response::Sealed { seed } => assert!(seed.len() > 4),
+      _ => unreachable!(),
+    }
+
+    agent_store.lock();
+    assert_eq!(seal(&agent_store, "0102"), error("Failed to encrypt seed"));
+  }
+
+  #[test]
+  fn test_socket_permissions() {
+    let path = std::env::temp_dir().join(format!("subwallet-agent-socket-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    let socket = socket_path(&path, "polkadot");
+    let listener = bind(&socket).unwrap();
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(socket.parent().unwrap()), 0o700);
+    assert_eq!(mode(&socket), 0o600);
+
+    let client = UnixStream::connect(&socket).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    assert_eq!(peer_uid(&server).unwrap(), unsafe { libc::getuid() });
+    assert_eq!(peer_uid(&client).unwrap(), unsafe { libc::getuid() });
+  }
+}
@@ -0,0 +1,590 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{ self, Write };
+use std::os::unix::io::AsRawFd;
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, SystemTime };
+use serde::Serialize;
+use rusqlite::{ params, Connection, OptionalExtension, TransactionBehavior, NO_PARAMS };
+use rustbreak::FileDatabase;
+use rustbreak::deser::Bincode;
+
+use crate::error::Result;
+use crate::migration::{ self, Schema };
+use crate::rpc::Config;
+
+/// Database file of the sqlite backend in data path
+const SQLITE_FILE: &str = "subwallet.db";
+/// Directory of automatic backups in data path
+const BACKUPS_DIR: &str = "backups";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+  /// One rustbreak file per key, the whole file is rewritten on every update
+  File,
+  /// All keys in one sqlite database, every update is a transaction
+  Sqlite,
+}
+
+impl Kind {
+  pub fn parse(name: &str) -> Result<Self> {
+    match name {
+      "file" => Ok(Kind::File),
+      "sqlite" => Ok(Kind::Sqlite),
+      _ => Err(format!("Unknown storage backend `{}`, `file` or `sqlite` is expected", name).into()),
+    }
+  }
+
+  /// The backend set in `[storage]` of `config.toml` in data path, `file` by default
+  pub fn configured(path: &Path) -> Result<Self> {
+    let config = Config::parse_from_file(&path.join("config.toml")).ok();
+    match config.and_then(|config| config.storage_backend()) {
+      Some(name) => Self::parse(&name),
+      None => Ok(Kind::File),
+    }
+  }
+}
+
+/// Persistent storage of one value, e.g. a wallet or the extrinsics of an address
+pub trait Backend<T> {
+  /// The latest value, it may be written by other processes
+  fn read(&self) -> Result<T>;
+
+  /// Modify the latest value and persist it
+  fn update(&self, f: &mut dyn FnMut(&mut T)) -> Result<()>;
+
+  /// `update` without a backup of the previous value, and delete the backups and migration copies of the key
+  /// under the same lock, e.g. the previous values hold secrets which the update seals
+  fn update_purging_history(&self, f: &mut dyn FnMut(&mut T)) -> Result<()>;
+}
+
+impl<T> dyn Backend<T> {
+  /// `update` with a `FnOnce` whose result is returned
+  pub fn write<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Result<R> {
+    let mut f = Some(f);
+    let mut result = None;
+    self.update(&mut |value| {
+      if let Some(f) = f.take() {
+        result = Some(f(value));
+      }
+    })?;
+    result.ok_or_else(|| "Storage backend did not run the update".into())
+  }
+
+  /// `update_purging_history` with a `FnOnce` whose result is returned
+  pub fn write_purging_history<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Result<R> {
+    let mut f = Some(f);
+    let mut result = None;
+    self.update_purging_history(&mut |value| {
+      if let Some(f) = f.take() {
+        result = Some(f(value));
+      }
+    })?;
+    result.ok_or_else(|| "Storage backend did not run the update".into())
+  }
+}
+
+/// Open the value of `key` by the configured backend, `key` is the file path relative to data path in the file backend.
+/// The latest `backups` previous values are kept in `backups/<key>.<milliseconds>`, they are in the format of the file backend
+pub fn open<T: Schema + Clone + Send + 'static>(path: &Path, key: &str, default: T, backups: usize) -> Result<Box<dyn Backend<T>>> {
+  let backups = Backups::new(path, key, backups);
+  match Kind::configured(path)? {
+    Kind::File => Ok(Box::new(FileBackend::open(path.join(key), default, backups)?)),
+    Kind::Sqlite => Ok(Box::new(SqliteBackend::open(path, key, default, backups)?)),
+  }
+}
+
+/// A file which is not imported into sqlite yet also counts
+pub fn exists(path: &Path, key: &str) -> bool {
+  if path.join(key).is_file() {
+    return true
+  }
+  match Kind::configured(path) {
+    Ok(Kind::Sqlite) => connect(path).and_then(|conn| Ok(select(&conn, key)?.is_some())).unwrap_or(false),
+    _ => false,
+  }
+}
+
+/// Sorted keys directly under `dir`, e.g. `wallets` lists `<name>` of `wallets/<name>`.
+/// Files with an extension, e.g. locks and temporary files, are not keys
+pub fn list(path: &Path, dir: &str) -> Vec<String> {
+  let mut names: Vec<String> = match fs::read_dir(path.join(dir)) {
+    Ok(entries) => entries.filter_map(|entry| {
+      let entry = entry.ok()?;
+      let name = entry.file_name().into_string().ok()?;
+      if entry.path().is_file() && !name.contains('.') {
+        Some(name)
+      } else {
+        None
+      }
+    }).collect(),
+    Err(_) => vec![],
+  };
+  if let Ok(Kind::Sqlite) = Kind::configured(path) {
+    let prefix = format!("{}/", dir);
+    let keys = connect(path).and_then(|conn| keys(&conn)).unwrap_or_default();
+    names.extend(keys.iter().filter_map(|key| key.strip_prefix(&prefix)).filter(|name| !name.contains('/')).map(String::from));
+  }
+  names.sort();
+  names.dedup();
+  names
+}
+
+/// Write `<file>.tmp` and rename it over `file`, so readers never see a partially written file
+pub fn write_atomic(file: &Path, data: &[u8]) -> Result<()> {
+  let tmp = migration::append_extension(file, "tmp");
+  let mut f = fs::File::create(&tmp)?;
+  f.write_all(data)?;
+  f.sync_all()?;
+  fs::rename(&tmp, file)?;
+  Ok(())
+}
+
+/// Advisory lock on `<file>.lock`, it is released on drop.
+/// The data file can not hold the lock, because it is replaced by `write_atomic`
+struct FileLock(fs::File);
+
+impl FileLock {
+  fn shared(file: &Path) -> Result<Self> {
+    Self::lock(file, libc::LOCK_SH)
+  }
+
+  fn exclusive(file: &Path) -> Result<Self> {
+    Self::lock(file, libc::LOCK_EX)
+  }
+
+  fn lock(file: &Path, operation: libc::c_int) -> Result<Self> {
+    let lock = fs::OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .open(migration::append_extension(file, "lock"))?;
+    if unsafe { libc::flock(lock.as_raw_fd(), operation) } != 0 {
+      return Err(io::Error::last_os_error().into())
+    }
+    Ok(Self(lock))
+  }
+}
+
+impl Drop for FileLock {
+  fn drop(&mut self) {
+    unsafe {
+      libc::flock(self.0.as_raw_fd(), libc::LOCK_UN);
+    }
+  }
+}
+
+/// Rotating copies of the previous values of a key
+struct Backups {
+  /// `backups/<key>`, the copies are `backups/<key>.<milliseconds>`
+  file: PathBuf,
+  keep: usize,
+}
+
+impl Backups {
+  fn new(path: &Path, key: &str, keep: usize) -> Self {
+    Self {
+      file: path.join(BACKUPS_DIR).join(key),
+      keep: keep,
+    }
+  }
+
+  /// Copy `data` and remove the oldest copies
+  fn save(&self, data: &[u8]) -> Result<()> {
+    if self.keep == 0 || data.is_empty() {
+      return Ok(())
+    }
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+    let backup = migration::append_extension(&self.file, &now.to_string());
+    if let Some(parent) = backup.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    write_atomic(&backup, data)?;
+
+    let backups = self.list();
+    if backups.len() > self.keep {
+      for backup in &backups[..backups.len() - self.keep] {
+        fs::remove_file(backup)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// From the oldest to the latest
+  fn list(&self) -> Vec<PathBuf> {
+    let (dir, name) = match (self.file.parent(), self.file.file_name()) {
+      (Some(dir), Some(name)) => (dir, format!("{}.", name.to_string_lossy())),
+      _ => return vec![],
+    };
+    let mut backups: Vec<(u128, PathBuf)> = match fs::read_dir(dir) {
+      Ok(entries) => entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let file_name = entry.file_name().into_string().ok()?;
+        let time = file_name.strip_prefix(&name)?.parse::<u128>().ok()?;
+        Some((time, entry.path()))
+      }).collect(),
+      Err(_) => vec![],
+    };
+    backups.sort();
+    backups.into_iter().map(|(_, backup)| backup).collect()
+  }
+
+  /// Delete all copies
+  fn clear(&self) -> Result<()> {
+    for backup in self.list() {
+      fs::remove_file(backup)?;
+    }
+    Ok(())
+  }
+}
+
+/// The rustbreak file database, updates are serialized by `FileLock` and written by `write_atomic`
+pub struct FileBackend<T: Schema + Clone + Send> {
+  db: FileDatabase<T, Bincode>,
+  file: PathBuf,
+  default: T,
+  backups: Backups,
+}
+
+impl<T: Schema + Clone + Send> FileBackend<T> {
+  fn open(file: PathBuf, default: T, backups: Backups) -> Result<Self> {
+    if let Some(parent) = file.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    let _lock = FileLock::exclusive(&file)?;
+    if let Some(backup) = migration::migrate_file::<T>(&file)? {
+      eprintln!("`{}` is upgraded, the old file is backed up to `{}`", file.display(), backup.display());
+    }
+    let db = FileDatabase::<T, Bincode>::load_from_path_or(file.clone(), default.clone()).map_err(|e| e.to_string())?;
+    Ok(Self {
+      db: db,
+      file: file,
+      default: default,
+      backups: backups,
+    })
+  }
+
+  /// Load the file written by other processes, the value is the default only if the file is missing or empty.
+  /// Other errors are returned, so an update is never applied to a stale value
+  fn reload(&self) -> Result<()> {
+    let missing = match fs::metadata(&self.file) {
+      Ok(metadata) => metadata.len() == 0,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => true,
+      Err(err) => return Err(err.into()),
+    };
+    if missing {
+      self.db.write(|data| *data = self.default.clone()).map_err(|e| e.to_string())?;
+      return Ok(())
+    }
+    self.db.load().map_err(|e| format!("Failed to read `{}`: {}", self.file.display(), e).into())
+  }
+
+  /// Apply `f` to the reloaded value, the encoded value is returned
+  fn apply(&self, f: &mut dyn FnMut(&mut T)) -> Result<Vec<u8>> {
+    self.reload()?;
+    self.db.write(|data| {
+      f(data);
+      encode(&*data)
+    }).map_err(|e| e.to_string())?
+  }
+}
+
+impl<T: Schema + Clone + Send> Backend<T> for FileBackend<T> {
+  fn read(&self) -> Result<T> {
+    let _lock = FileLock::shared(&self.file)?;
+    self.reload()?;
+    let data = self.db.borrow_data().map_err(|e| e.to_string())?;
+    Ok(data.clone())
+  }
+
+  fn update(&self, f: &mut dyn FnMut(&mut T)) -> Result<()> {
+    let _lock = FileLock::exclusive(&self.file)?;
+    let encoded = self.apply(f)?;
+    if let Ok(previous) = fs::read(&self.file) {
+      self.backups.save(&previous)?;
+    }
+    write_atomic(&self.file, &encoded)
+  }
+
+  fn update_purging_history(&self, f: &mut dyn FnMut(&mut T)) -> Result<()> {
+    let _lock = FileLock::exclusive(&self.file)?;
+    let encoded = self.apply(f)?;
+    write_atomic(&self.file, &encoded)?;
+    self.backups.clear()?;
+    migration::remove_backups(&self.file)
+  }
+}
+
+/// One row of the sqlite database in data path
+pub struct SqliteBackend<T> {
+  conn: RefCell<Connection>,
+  key: String,
+  /// The file of the key in the file backend, it may be imported
+  file: PathBuf,
+  default: T,
+  backups: Backups,
+}
+
+impl<T: Schema + Clone> SqliteBackend<T> {
+  /// The file of `key` written by the file backend is imported if the row does not exist
+  fn open(path: &Path, key: &str, default: T, backups: Backups) -> Result<Self> {
+    let conn = connect(path)?;
+    let file = path.join(key);
+    if select(&conn, key)?.is_none() && file.is_file() {
+      let _lock = FileLock::exclusive(&file)?;
+      migration::migrate_file::<T>(&file)?;
+      let data = fs::read(&file)?;
+      if !data.is_empty() {
+        let value: T = decode(&data)?;
+        conn.execute("INSERT OR IGNORE INTO data (key, value) VALUES (?1, ?2)", params![key, encode(&value)?])?;
+        eprintln!("`{}` is imported into `{}`", file.display(), path.join(SQLITE_FILE).display());
+      }
+    }
+    Ok(Self {
+      conn: RefCell::new(conn),
+      key: key.to_owned(),
+      file: file,
+      default: default,
+      backups: backups,
+    })
+  }
+}
+
+impl<T: Schema + Clone> Backend<T> for SqliteBackend<T> {
+  fn read(&self) -> Result<T> {
+    match select(&self.conn.borrow(), &self.key)? {
+      Some(data) => decode(&data),
+      None => Ok(self.default.clone()),
+    }
+  }
+
+  fn update(&self, f: &mut dyn FnMut(&mut T)) -> Result<()> {
+    let mut conn = self.conn.borrow_mut();
+    // the write lock is taken at the beginning, so other processes can not write between read and write
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let previous = select(&tx, &self.key)?;
+    let mut value = match &previous {
+      Some(data) => decode(data)?,
+      None => self.default.clone(),
+    };
+    f(&mut value);
+    if let Some(previous) = previous {
+      self.backups.save(&previous)?;
+    }
+    tx.execute("INSERT OR REPLACE INTO data (key, value) VALUES (?1, ?2)", params![self.key, encode(&value)?])?;
+    tx.commit()?;
+    Ok(())
+  }
+
+  fn update_purging_history(&self, f: &mut dyn FnMut(&mut T)) -> Result<()> {
+    let mut conn = self.conn.borrow_mut();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let mut value = match select(&tx, &self.key)? {
+      Some(data) => decode(&data)?,
+      None => self.default.clone(),
+    };
+    f(&mut value);
+    tx.execute("INSERT OR REPLACE INTO data (key, value) VALUES (?1, ?2)", params![self.key, encode(&value)?])?;
+    // the file imported from the file backend and its migration copies hold the previous value too
+    if self.file.is_file() {
+      fs::remove_file(&self.file)?;
+    }
+    migration::remove_backups(&self.file)?;
+    self.backups.clear()?;
+    tx.commit()?;
+    Ok(())
+  }
+}
+
+fn connect(path: &Path) -> Result<Connection> {
+  fs::create_dir_all(path)?;
+  let conn = Connection::open(path.join(SQLITE_FILE))?;
+  conn.busy_timeout(Duration::from_secs(10))?;
+  // deleted and replaced values are overwritten, they may hold seeds before they are sealed
+  conn.execute_batch("PRAGMA secure_delete = ON")?;
+  conn.execute("CREATE TABLE IF NOT EXISTS data (key TEXT PRIMARY KEY, value BLOB NOT NULL)", NO_PARAMS)?;
+  Ok(conn)
+}
+
+fn select(conn: &Connection, key: &str) -> Result<Option<Vec<u8>>> {
+  let data = conn.query_row("SELECT value FROM data WHERE key = ?1", params![key], |row| row.get(0)).optional()?;
+  Ok(data)
+}
+
+fn keys(conn: &Connection) -> Result<Vec<String>> {
+  let mut stmt = conn.prepare("SELECT key FROM data")?;
+  let keys = stmt.query_map(NO_PARAMS, |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+  Ok(keys)
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+  Ok(bincode::serialize(value).map_err(|e| e.to_string())?)
+}
+
+/// Rows are written in the current layout, older layouts are migrated when they are read
+fn decode<T: Schema>(data: &[u8]) -> Result<T> {
+  let version = migration::version_of(data);
+  if version == T::VERSION {
+    Ok(bincode::deserialize(data).map_err(|e| e.to_string())?)
+  } else {
+    T::migrate(version, data)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::store::{ Extrinsic, Extrinsics };
+
+  fn setup(name: &str, backend: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("subwallet-backend-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("config.toml"), format!("[rpc]\n\n[storage]\nbackend = \"{}\"\n", backend)).unwrap();
+    dir
+  }
+
+  fn xt(block_number: u32) -> Extrinsic {
+    Extrinsic {
+      block_number: block_number,
+      block_hash: String::new(),
+      index: 1,
+      signer: None,
+      status: "success".to_string(),
+      module: "Balances".to_string(),
+      call: "transfer".to_string(),
+    }
+  }
+
+  fn check_backend(path: &Path) {
+    let db = open(path, "xt-a", Extrinsics::new("a".to_string()), 0).unwrap();
+    assert_eq!(db.read().unwrap(), Extrinsics::new("a".to_string()));
+
+    db.write(|xts| xts.add(xt(10))).unwrap();
+    // another handle, e.g. in another process, sees the update and does not overwrite it
+    let other = open(path, "xt-a", Extrinsics::new("a".to_string()), 0).unwrap();
+    other.write(|xts| xts.add(xt(11))).unwrap();
+    let scanned_at = db.write(|xts| xts.scanned_at).unwrap();
+    assert_eq!(scanned_at, 11);
+    let xts = db.read().unwrap();
+    assert!(xts.get(10, 1).is_some() && xts.get(11, 1).is_some());
+    assert!(exists(path, "xt-a"));
+
+    open(path, "wallets/b", Extrinsics::new("b".to_string()), 0).unwrap().write(|_| ()).unwrap();
+    open(path, "wallets/c", Extrinsics::new("c".to_string()), 0).unwrap().write(|_| ()).unwrap();
+    assert_eq!(list(path, "wallets"), vec!["b".to_string(), "c".to_string()]);
+  }
+
+  #[test]
+  fn test_file_backend() {
+    let path = setup("file", "file");
+    check_backend(&path);
+    assert!(path.join("xt-a").is_file());
+  }
+
+  #[test]
+  fn test_sqlite_backend() {
+    let path = setup("sqlite", "sqlite");
+    check_backend(&path);
+    assert!(!path.join("xt-a").exists());
+    assert!(path.join(SQLITE_FILE).is_file());
+  }
+
+  #[test]
+  fn test_sqlite_import_file() {
+    let path = setup("import", "sqlite");
+    let addr = "14TouV8puYdaN72wMvNirvZsvcvYk5GRfTwJ7XF4P9fibL3m";
+    let key = format!("xt-{}", addr);
+    fs::copy("tests/fixtures/xt_v0.bin", path.join(&key)).unwrap();
+
+    let db = open(&path, &key, Extrinsics::new(addr.to_string()), 0).unwrap();
+    let xts = db.read().unwrap();
+    assert_eq!(xts.scanned_at, 100);
+    assert!(xts.get(100, 1).is_some());
+    // the upgraded file is kept, the row is used from now on
+    db.write(|xts| xts.scanned_at = 200).unwrap();
+    let db = open(&path, &key, Extrinsics::new(addr.to_string()), 0).unwrap();
+    assert_eq!(db.read().unwrap().scanned_at, 200);
+  }
+
+  fn check_concurrent_writes(path: &Path) {
+    let workers: Vec<_> = (0..4u32).map(|i| {
+      let path = path.to_path_buf();
+      std::thread::spawn(move || {
+        let db = open(&path, "xt-c", Extrinsics::new("c".to_string()), 0).unwrap();
+        for n in 0..10 {
+          db.write(|xts| xts.add(xt(i * 100 + n))).unwrap();
+        }
+      })
+    }).collect();
+    for worker in workers {
+      worker.join().unwrap();
+    }
+    let xts = open(path, "xt-c", Extrinsics::new("c".to_string()), 0).unwrap().read().unwrap();
+    for i in 0..4u32 {
+      for n in 0..10 {
+        assert!(xts.get(i * 100 + n, 1).is_some());
+      }
+    }
+  }
+
+  #[test]
+  fn test_concurrent_writes() {
+    check_concurrent_writes(&setup("concurrent-file", "file"));
+    check_concurrent_writes(&setup("concurrent-sqlite", "sqlite"));
+  }
+
+  #[test]
+  fn test_backups() {
+    for kind in vec!["file", "sqlite"] {
+      let path = setup(&format!("backups-{}", kind), kind);
+      let db = open(&path, "wallets/d", Extrinsics::new("d".to_string()), 2).unwrap();
+      for n in 1..=4 {
+        db.write(|xts| xts.scanned_at = n).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+      }
+      let backups = Backups::new(&path, "wallets/d", 2).list();
+      assert_eq!(backups.len(), 2);
+      // the latest backup is the value before the last update
+      let latest: Extrinsics = decode(&fs::read(&backups[1]).unwrap()).unwrap();
+      assert_eq!(latest.scanned_at, 3);
+      assert!(backups[0].starts_with(path.join(BACKUPS_DIR).join("wallets")));
+
+      // no copy of the previous values is left
+      db.write_purging_history(|xts| xts.scanned_at = 5).unwrap();
+      assert!(Backups::new(&path, "wallets/d", 2).list().is_empty());
+      assert_eq!(db.read().unwrap().scanned_at, 5);
+    }
+  }
+
+  #[test]
+  fn test_reload_errors() {
+    let path = setup("reload", "file");
+    let file = path.join("xt-e");
+    let db = open(&path, "xt-e", Extrinsics::new("e".to_string()), 0).unwrap();
+    db.write(|xts| xts.add(xt(10))).unwrap();
+
+    // a truncated file is an error, the update is not applied to the value loaded before
+    let data = fs::read(&file).unwrap();
+    fs::write(&file, &data[..data.len() / 2]).unwrap();
+    assert!(db.read().is_err());
+    assert!(db.write(|xts| xts.add(xt(11))).is_err());
+    assert_eq!(fs::read(&file).unwrap(), &data[..data.len() / 2]);
+
+    // a missing or empty file is the default value
+    fs::write(&file, b"").unwrap();
+    assert_eq!(db.read().unwrap(), Extrinsics::new("e".to_string()));
+    fs::remove_file(&file).unwrap();
+    db.write(|xts| xts.add(xt(12))).unwrap();
+    let xts = db.read().unwrap();
+    assert!(xts.get(10, 1).is_none() && xts.get(12, 1).is_some());
+  }
+
+  #[test]
+  fn test_kind() {
+    assert_eq!(Kind::parse("sqlite").unwrap(), Kind::Sqlite);
+    assert!(Kind::parse("sled").is_err());
+    assert_eq!(Kind::configured(&setup("kind", "file")).unwrap(), Kind::File);
+    assert_eq!(Kind::configured(Path::new("/nonexistent")).unwrap(), Kind::File);
+  }
+}
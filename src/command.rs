@@ -1,10 +1,56 @@
-use clap::{App, SubCommand, Arg};
+use clap::{App, AppSettings, SubCommand, Arg};
 
 pub fn get_app<'a, 'b>() -> App<'a, 'b> {
   App::new("subwallet")
       .author("yxf <yxf4559@gmail.com>")
       .about("A simple Command Line Interface wallet for Polkadot/Substrate.")
       .version(env!("CARGO_PKG_VERSION"))
+      .arg(Arg::with_name("wallet")
+        .short("w")
+        .long("wallet")
+        .value_name("name")
+        .takes_value(true)
+        .global(true)
+        .help("The wallet to use instead of the default wallet")
+      )
+      .arg(Arg::with_name("network")
+        .long("network")
+        .value_name("name")
+        .takes_value(true)
+        .global(true)
+        .help("The network to use instead of the default network, e.g. polkadot, kusama, westend")
+      )
+      .arg(Arg::with_name("datadir")
+        .short("d")
+        .long("datadir")
+        .value_name("path")
+        .takes_value(true)
+        .global(true)
+        .help("The directory of wallets, config.toml and extrinsics, default is $SUBWALLET_HOME or ~/.subwallet")
+      )
+      .arg(Arg::with_name("profile")
+        .short("P")
+        .long("profile")
+        .value_name("name")
+        .takes_value(true)
+        .global(true)
+        .help("Use the isolated data directory `profiles/<name>` in the data directory")
+      )
+      .arg(Arg::with_name("rpc-record")
+        .long("rpc-record")
+        .value_name("file")
+        .takes_value(true)
+        .global(true)
+        .help("Record rpc requests and responses to a JSON file, which can be replayed by `--rpc-replay`")
+      )
+      .arg(Arg::with_name("rpc-replay")
+        .long("rpc-replay")
+        .value_name("file")
+        .takes_value(true)
+        .global(true)
+        .conflicts_with("rpc-record")
+        .help("Replay rpc responses recorded by `--rpc-record` instead of connecting to a node")
+      )
       .subcommands(vec![
         SubCommand::with_name("getnewaddress")
           .about("Generate a new address associated with label, deafult cryptography is sr25519")
@@ -15,20 +61,143 @@ pub fn get_app<'a, 'b>() -> App<'a, 'b> {
             -e, --ed25519 'Use Ed25519/BIP39 cryptography'
             -k, --ecdsa   'Use SECP256k1/ECDSA/BIP39 cryptography'
             -s, --sr25519 'Use Schnorr/Ristretto x25519/BIP39 cryptography'
+            -n, --no-phrase 'Do not show and keep the mnemonic phrase'
+            -p, --path [path] 'Derivation path of secret URI, e.g. //polkadot//0/1///password'
+          "),
+        SubCommand::with_name("getvanityaddress")
+          .about("Generate a new address matching the patterns, the mnemonic phrase is not available")
+          .arg(Arg::with_name("label")
+            .help("The label name for the address to be linked to.")
+            .required(true)
+          ).args_from_usage("
+            -e, --ed25519 'Use Ed25519/BIP39 cryptography'
+            -k, --ecdsa   'Use SECP256k1/ECDSA/BIP39 cryptography'
+            -s, --sr25519 'Use Schnorr/Ristretto x25519/BIP39 cryptography'
+            --prefix [prefix] 'The address starts with it, case-insensitive'
+            --suffix [suffix] 'The address ends with it, case-insensitive'
+            --contains [contains] 'The address contains it, case-insensitive'
+          "),
+        SubCommand::with_name("derive")
+          .about("Derive a new address from the seed of parent address")
+          .args_from_usage("
+            <parent> 'The parent address or label'
+            <path> 'Derivation path appended to the path of parent, e.g. //polkadot//0/1'
+            <label> 'The label name for the new address'
+          "),
+        SubCommand::with_name("showphrase")
+          .about("Show the mnemonic phrase of address, password is required")
+          .args_from_usage("
+            <label> 'The address or label'
+          "),
+        SubCommand::with_name("importphrase")
+          .about("Import address from mnemonic phrase, deafult cryptography is sr25519")
+          .arg(Arg::with_name("label")
+            .help("The label name for the address to be linked to.")
+            .required(true)
+          ).args_from_usage("
+            -e, --ed25519 'Use Ed25519/BIP39 cryptography'
+            -k, --ecdsa   'Use SECP256k1/ECDSA/BIP39 cryptography'
+            -s, --sr25519 'Use Schnorr/Ristretto x25519/BIP39 cryptography'
+            -a, --address [address] 'The expected address, import fails if the derived address does not match it'
+            -p, --path [path] 'Derivation path of secret URI, e.g. //polkadot//0/1///password'
+          "),
+        SubCommand::with_name("createwallet")
+          .about("Create a new empty wallet")
+          .args_from_usage("
+            <name> 'The wallet name, letters, numbers, `-` and `_` are allowed'
+          "),
+        SubCommand::with_name("listwallets")
+          .about("Prints the list of wallets, the current wallet is marked with `*`"),
+        SubCommand::with_name("loadwallet")
+          .about("Set the default wallet, which is used when `--wallet` is not given")
+          .args_from_usage("
+            <name> 'The wallet name'
+          "),
+        SubCommand::with_name("encryptwallet")
+          .about("Encrypt seeds of the wallet with a passphrase"),
+        SubCommand::with_name("changepassphrase")
+          .about("Change the passphrase of encrypted wallet"),
+        SubCommand::with_name("walletpassphrase")
+          .about("Unlock the encrypted wallet for a period, the keys are held by a local agent")
+          .args_from_usage("
+            <timeout> 'Seconds to keep the wallet unlocked'
+          "),
+        SubCommand::with_name("walletlock")
+          .about("Lock the wallet unlocked by `walletpassphrase` immediately"),
+        SubCommand::with_name("agent")
+          .setting(AppSettings::Hidden)
+          .about("Run unlock agent, it is started by `walletpassphrase`")
+          .args_from_usage("
+            <timeout> 'Seconds to keep the wallet unlocked'
           "),
         SubCommand::with_name("listaddresses")
-          .about("Prints the list of addresses"),
+          .about("Prints the list of addresses")
+          .args_from_usage("
+            -a, --all 'Include archived addresses'
+          "),
+        SubCommand::with_name("checkwallet")
+          .about("Report labels used by more than one address and labels which look like addresses"),
+        SubCommand::with_name("setlabel")
+          .about("Change the label of address")
+          .args_from_usage("
+            <label_or_address> 'The address or label'
+            <new_label> 'The new label'
+          "),
+        SubCommand::with_name("removeaddress")
+          .about("Remove address from wallet, the address with seed must be backed up first")
+          .args_from_usage("
+            <label_or_address> 'The address or label'
+            -f, --force 'Remove the address even if it has no verified backup'
+          "),
+        SubCommand::with_name("archiveaddress")
+          .about("Hide address from `listaddresses` and `getbalances`, it can still be used by label")
+          .args_from_usage("
+            <label_or_address> 'The address or label'
+            -u, --undo 'Unarchive the address'
+          "),
 
         SubCommand::with_name("restore")
-          .about("Restore address from json file")
+          .about("Restore address from json file, batch file of all accounts is also supported")
           .args_from_usage("
             <file>  'The filename with path'
+            -l, --label [label] 'The label of restored address instead of the name in keystore'
           "),
         SubCommand::with_name("backup")
           .about("Backup specified address to local json file")
+          .arg(Arg::with_name("label")
+            .help("Address or label to backup")
+            .required_unless("all")
+          )
+          .arg(Arg::with_name("path")
+            .help("The destination directory or file")
+            .required_unless("all")
+          )
+          .arg(Arg::with_name("all")
+            .short("a")
+            .long("all")
+            .value_name("path")
+            .takes_value(true)
+            .conflicts_with_all(&["label", "path", "legacy"])
+            .help("Backup all addresses into one batch file")
+          )
+          .args_from_usage("
+            -l, --legacy 'Write keystore in version 2 format'
+          "),
+        SubCommand::with_name("splitbackup")
+          .about("Split the seed of address into shares, any `threshold` of them can recover the address")
           .args_from_usage("
-            <label>  'Address or label to backup'
-            <path>  'The destination directory or file'
+            <label> 'Address or label to backup'
+            -t, --threshold <threshold> 'Number of shares required to recover'
+            -n, --shares <shares> 'Total number of shares'
+            -o, --output [dir] 'Write shares to json files in the directory instead of printing word lists'
+          "),
+        SubCommand::with_name("combinebackup")
+          .about("Recover address from shares created by `splitbackup`")
+          .args_from_usage("
+            <label> 'The label name for the recovered address'
+            [files]... 'Json files of shares, word lists are asked if no file is given'
+            -a, --address [address] 'The address to recover, required for word lists'
+            -p, --path [path] 'Derivation junctions of the address for word lists, e.g. //polkadot//0'
           "),
         SubCommand::with_name("getbalances")
           .about("Query balances of addresses"),
@@ -47,9 +216,21 @@ pub fn get_app<'a, 'b>() -> App<'a, 'b> {
             <label_or_address> 'The Address or label'
           "),
         SubCommand::with_name("setrpcurl")
-          .about("Save RPC url")
+          .about("Save RPC urls of the network, the first one is preferred and the others are used when it fails")
+          .args_from_usage("
+            <url>... 'RPC urls in order of priority, exmaple: wss://rpc.polkadot.io'
+          "),
+        SubCommand::with_name("devnode")
+          .about("Run an in-memory development node which serves JSON-RPC over WebSocket and HTTP, it only supports balance transfers")
+          .args_from_usage("
+            --port [port] 'WebSocket port, default is 9944'
+            --http-port [port] 'HTTP port, default is 9933'
+            --endow [address]... 'Addresses endowed at genesis besides //Alice and //Bob'
+          "),
+        SubCommand::with_name("setnetwork")
+          .about("Set the default network, which is used when `--network` is not given")
           .args_from_usage("
-            <url> 'RPC url, exmaple: wss://rpc.polkadot.io'
+            <name> 'The network name in the registry'
           "),
         SubCommand::with_name("watchaddress")
           .about("Add a watchonly address")
@@ -1,9 +1,38 @@
 
 pub use sp_core::{
-  crypto::{set_default_ss58_version, Ss58AddressFormat, Ss58Codec, Derive, AccountId32 },
+  crypto::{set_default_ss58_version, Ss58AddressFormat, Ss58Codec, Derive, DeriveJunction, AccountId32 },
   ed25519, sr25519, ecdsa, Pair, Public,
 };
 use blake2_rfc::blake2b::{ Blake2b, Blake2bResult };
+use regex::Regex;
+use zeroize::Zeroizing;
+
+/// Derivation part of Substrate secret URI, e.g. `//polkadot//0/1///password`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DerivationPath {
+  /// Hard and soft junctions, e.g. `//polkadot//0/1`
+  pub path: String,
+  /// Password of the mnemonic phrase, it is never saved
+  pub password: Option<Zeroizing<String>>,
+}
+
+impl DerivationPath {
+  pub fn parse(s: &str) -> Result<Self, ()> {
+    let re = Regex::new(r"^(?P<path>(//?[^/]+)*)(///(?P<password>.*))?$").unwrap();
+    let cap = re.captures(s).ok_or(())?;
+    let path = cap.name("path").map(|m| m.as_str()).unwrap_or("");
+    let password = cap.name("password").map(|m| Zeroizing::new(m.as_str().to_string()));
+    Ok(Self {
+      path: path.to_string(),
+      password: password,
+    })
+  }
+
+  pub fn junctions(path: &str) -> Vec<DeriveJunction> {
+    let re = Regex::new(r"/(/?[^/]+)").unwrap();
+    re.captures_iter(path).map(|cap| DeriveJunction::from(&cap[1])).collect()
+  }
+}
 
 pub trait Crypto: Sized {
   type Pair: Pair<Public = Self::Public>;
@@ -11,6 +40,16 @@ pub trait Crypto: Sized {
 
   fn pair_from_secret_slice(slice: &[u8]) -> Result<Self::Pair, ()>;
 
+  /// Make key pair from secret then derive it by junctions of `path`, e.g. `//polkadot//0/1`
+  fn pair_from_secret_slice_with_path(slice: &[u8], path: &str) -> Result<Self::Pair, ()> {
+    let pair = Self::pair_from_secret_slice(slice)?;
+    if path.is_empty() {
+      return Ok(pair)
+    }
+    let junctions = DerivationPath::junctions(path);
+    pair.derive(junctions.into_iter(), None).map(|(pair, _)| pair).map_err(|_| () )
+  }
+
   fn crypto_type() -> &'static str;
 
   fn to_address<P: Pair>(pair: &P) -> String;
@@ -109,3 +148,44 @@ impl Crypto for Sr25519 {
 }
 
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_derivation_path() {
+    let path = DerivationPath::parse("//polkadot//0/1///secret").unwrap();
+    assert_eq!(path.path, "//polkadot//0/1");
+    assert_eq!(path.password.as_ref().map(|v| v.as_str()), Some("secret"));
+
+    let path = DerivationPath::parse("//polkadot").unwrap();
+    assert_eq!(path.path, "//polkadot");
+    assert_eq!(path.password, None);
+
+    assert_eq!(DerivationPath::parse("").unwrap(), DerivationPath::default());
+    assert!(DerivationPath::parse("polkadot").is_err());
+  }
+
+  #[test]
+  fn test_junctions() {
+    let junctions = DerivationPath::junctions("//polkadot//0/1");
+    assert_eq!(junctions, vec![
+      DeriveJunction::hard("polkadot"),
+      DeriveJunction::hard(0u64),
+      DeriveJunction::soft(1u64),
+    ]);
+  }
+
+  #[test]
+  fn test_pair_from_secret_slice_with_path() {
+    let phrase = "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+    let (_, seed) = sr25519::Pair::from_phrase(phrase, None).unwrap();
+    let expect = sr25519::Pair::from_string(&format!("{}//Alice", phrase), None).unwrap();
+    let pair = Sr25519::pair_from_secret_slice_with_path(&seed[..], "//Alice").unwrap();
+    assert_eq!(pair.public(), expect.public());
+
+    let (_, seed) = ed25519::Pair::from_phrase(phrase, None).unwrap();
+    assert!(Ed25519::pair_from_secret_slice_with_path(&seed[..], "//Alice").is_ok());
+    assert!(Ed25519::pair_from_secret_slice_with_path(&seed[..], "/soft").is_err());
+  }
+}
@@ -0,0 +1,513 @@
+use std::collections::BTreeMap;
+use std::sync::{ Arc, Mutex };
+
+use async_std::net::{ TcpListener, TcpStream };
+use async_std::io::BufReader;
+use async_std::task;
+use async_tungstenite::tungstenite::Message;
+use futures::{ SinkExt, StreamExt };
+use futures::io::{ AsyncBufReadExt, AsyncReadExt, AsyncWriteExt };
+use codec::{ Decode, Encode };
+use serde::de::DeserializeOwned;
+use serde_json::{ json, Value };
+use sp_core::{ blake2_256, twox_128, Bytes, H256 };
+use sp_core::storage::{ StorageChangeSet, StorageData, StorageKey };
+use sp_runtime::generic::Digest;
+use sp_runtime::traits::{ Header as _, Verify };
+use frame_support::weights::DispatchInfo;
+use frame_system::{ EventRecord, Phase, RawEvent };
+use runtime::{ Block, BalancesCall, Call, Event, Header, SignedBlock, SignedPayload, UncheckedExtrinsic, VERSION };
+
+use crate::error::Result;
+use crate::primitives::{ AccountId, AccountInfo, Balance, BlockNumber, Hash };
+use crate::rpc::{ account_key, RuntimeVersion };
+use crate::transfer;
+
+/// Decimals of the `dev` network
+pub const DECIMALS: u32 = 12;
+/// Balance of every endowed account at genesis, in units
+pub const ENDOWMENT: Balance = 1_000_000;
+/// Largest HTTP request body, a transfer is a few hundred bytes
+const MAX_BODY_SIZE: usize = 1 << 20;
+
+type Storage = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// JSON-RPC error object
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+  pub code: i64,
+  pub message: String,
+}
+
+impl RpcError {
+  fn invalid_params(message: String) -> Self {
+    Self { code: -32602, message }
+  }
+
+  /// Error code of substrate for invalid transactions
+  fn invalid_transaction(message: &str) -> Self {
+    Self { code: 1010, message: format!("Invalid Transaction: {}", message) }
+  }
+}
+
+struct BlockState {
+  hash: Hash,
+  block: SignedBlock,
+  /// Storage after the block is imported
+  storage: Storage,
+}
+
+/// An in-memory chain which only knows balance transfers, a block is produced for every extrinsic submitted
+pub struct Chain {
+  blocks: Vec<BlockState>,
+}
+
+fn events_key() -> Vec<u8> {
+  let mut key = twox_128(b"System").to_vec();
+  key.extend(twox_128(b"Events").to_vec());
+  key
+}
+
+fn param<T: DeserializeOwned>(params: &Value, index: usize) -> std::result::Result<Option<T>, RpcError> {
+  match params.get(index) {
+    None | Some(Value::Null) => Ok(None),
+    Some(value) => serde_json::from_value(value.clone())
+      .map(Some)
+      .map_err(|e| RpcError::invalid_params(format!("Invalid param #{}: {}", index, e))),
+  }
+}
+
+/// Block number as number or hex string
+fn block_number(params: &Value) -> std::result::Result<Option<u64>, RpcError> {
+  match params.get(0) {
+    None | Some(Value::Null) => Ok(None),
+    Some(Value::Number(n)) => n.as_u64().map(Some).ok_or(RpcError::invalid_params("Invalid block number".into())),
+    Some(Value::String(s)) => u64::from_str_radix(s.trim_start_matches("0x"), 16)
+      .map(Some)
+      .map_err(|_| RpcError::invalid_params("Invalid block number".into())),
+    Some(_) => Err(RpcError::invalid_params("Invalid block number".into())),
+  }
+}
+
+fn to_value<T: serde::Serialize>(value: T) -> std::result::Result<Value, RpcError> {
+  serde_json::to_value(value).map_err(|e| RpcError { code: -32603, message: e.to_string() })
+}
+
+impl Chain {
+  /// Genesis with `endowed` accounts, each of them has `ENDOWMENT` units
+  pub fn new(endowed: &[AccountId]) -> Self {
+    let mut storage = Storage::new();
+    for account in endowed {
+      let mut info = AccountInfo::default();
+      info.data.free = ENDOWMENT * 10u128.pow(DECIMALS);
+      storage.insert(account_key(account).0, info.encode());
+    }
+    storage.insert(events_key(), Vec::<EventRecord<Event, Hash>>::new().encode());
+    let mut chain = Self { blocks: vec![] };
+    chain.push_block(vec![], storage);
+    chain
+  }
+
+  pub fn genesis_hash(&self) -> Hash {
+    self.blocks[0].hash
+  }
+
+  fn best(&self) -> &BlockState {
+    self.blocks.last().unwrap()
+  }
+
+  fn at(&self, hash: Option<Hash>) -> std::result::Result<&BlockState, RpcError> {
+    match hash {
+      None => Ok(self.best()),
+      Some(hash) => self.blocks.iter().find(|b| b.hash == hash)
+        .ok_or(RpcError::invalid_params(format!("Unknown block {:#x}", hash))),
+    }
+  }
+
+  fn push_block(&mut self, extrinsics: Vec<UncheckedExtrinsic>, storage: Storage) {
+    let number = self.blocks.len() as BlockNumber;
+    let parent_hash = self.blocks.last().map(|b| b.hash).unwrap_or_default();
+    let extrinsics_root = H256::from(blake2_256(&extrinsics.encode()));
+    let state_root = H256::from(blake2_256(&storage.encode()));
+    let header = Header::new(number, extrinsics_root, state_root, parent_hash, Digest::default());
+    let hash = header.hash();
+    let block = SignedBlock {
+      block: Block { header, extrinsics },
+      justification: None,
+    };
+    self.blocks.push(BlockState { hash, block, storage });
+  }
+
+  pub fn account_info(&self, account: &AccountId) -> AccountInfo {
+    self.best().storage.get(&account_key(account).0)
+      .and_then(|data| AccountInfo::decode(&mut &data[..]).ok())
+      .unwrap_or_default()
+  }
+
+  /// Check the extrinsic like `transfer::make_extrinsic` signs it, and apply the transfer in a new block
+  pub fn submit(&mut self, bytes: &[u8]) -> std::result::Result<Hash, RpcError> {
+    let xt = UncheckedExtrinsic::decode(&mut &bytes[..])
+      .map_err(|_| RpcError::invalid_transaction("Could not decode extrinsic"))?;
+    let (signer, signature, extra) = xt.signature.clone().ok_or(RpcError::invalid_transaction("Extrinsic is not signed"))?;
+    let (dest, value) = match xt.function.clone() {
+      Call::Balances(BalancesCall::transfer(dest, value)) => (dest, value),
+      _ => return Err(RpcError::invalid_transaction("Only Balances::transfer is supported by devnode")),
+    };
+
+    let mut from = self.account_info(&signer);
+    let version = RuntimeVersion::compiled();
+    let (function, expected_extra, additional) = transfer::make_payload(xt.function.clone(), from.nonce, self.genesis_hash(), &version).deconstruct();
+    if extra.encode() != expected_extra.encode() {
+      return Err(RpcError::invalid_transaction("Transaction is outdated or in the future, or its extensions are unknown"))
+    }
+    let payload = SignedPayload::from_raw(function, expected_extra, additional);
+    if !payload.using_encoded(|payload| signature.verify(payload, &signer)) {
+      return Err(RpcError::invalid_transaction("Transaction has a bad signature"))
+    }
+    if from.data.free < value {
+      return Err(RpcError::invalid_transaction("Inability to pay some fees (e.g. account balance too low)"))
+    }
+
+    let mut storage = self.best().storage.clone();
+    from.nonce += 1;
+    from.data.free -= value;
+    storage.insert(account_key(&signer).0, from.encode());
+    // the info of `dest` is read after `from` is written, so a transfer to self keeps the balance
+    let mut to = storage.get(&account_key(&dest).0)
+      .and_then(|data| AccountInfo::decode(&mut &data[..]).ok())
+      .unwrap_or_default();
+    to.data.free += value;
+    storage.insert(account_key(&dest).0, to.encode());
+
+    let events = vec![
+      EventRecord {
+        phase: Phase::ApplyExtrinsic(0),
+        event: Event::balances(balances::RawEvent::Transfer(signer, dest, value)),
+        topics: vec![],
+      },
+      EventRecord {
+        phase: Phase::ApplyExtrinsic(0),
+        event: Event::system(RawEvent::ExtrinsicSuccess(DispatchInfo::default())),
+        topics: vec![],
+      },
+    ];
+    storage.insert(events_key(), events.encode());
+
+    let hash = H256::from(blake2_256(bytes));
+    self.push_block(vec![xt], storage);
+    Ok(hash)
+  }
+
+  /// Changes of `keys` in blocks from `from` to `to`, the values in `from` are all reported
+  fn query_storage(&self, keys: &[StorageKey], from: Hash, to: Option<Hash>) -> std::result::Result<Vec<StorageChangeSet<Hash>>, RpcError> {
+    let start = self.blocks.iter().position(|b| b.hash == from).ok_or(RpcError::invalid_params(format!("Unknown block {:#x}", from)))?;
+    let end = match to {
+      Some(to) => self.blocks.iter().position(|b| b.hash == to).ok_or(RpcError::invalid_params(format!("Unknown block {:#x}", to)))?,
+      None => self.blocks.len() - 1,
+    };
+    let mut sets = vec![];
+    for number in start..=end {
+      let block = &self.blocks[number];
+      let changes: Vec<(StorageKey, Option<StorageData>)> = keys.iter()
+        .filter(|key| number == start || self.blocks[number - 1].storage.get(&key.0) != block.storage.get(&key.0))
+        .map(|key| (key.clone(), block.storage.get(&key.0).cloned().map(StorageData)))
+        .collect();
+      if !changes.is_empty() {
+        sets.push(StorageChangeSet { block: block.hash, changes });
+      }
+    }
+    Ok(sets)
+  }
+
+  /// Answer a JSON-RPC request
+  pub fn handle(&mut self, method: &str, params: &Value) -> std::result::Result<Value, RpcError> {
+    match method {
+      "system_health" => Ok(json!({ "peers": 0, "isSyncing": false, "shouldHavePeers": false })),
+      "system_chain" => Ok(json!("Development")),
+      "system_properties" => Ok(json!({ "ss58Format": 42, "tokenDecimals": DECIMALS, "tokenSymbol": "UNIT" })),
+      "state_getRuntimeVersion" => Ok(json!({
+        "specName": VERSION.spec_name.to_string(),
+        "implName": VERSION.impl_name.to_string(),
+        "authoringVersion": VERSION.authoring_version,
+        "specVersion": VERSION.spec_version,
+        "implVersion": VERSION.impl_version,
+        "apis": [],
+        "transactionVersion": VERSION.transaction_version,
+      })),
+      "state_getMetadata" => to_value(Bytes(runtime::Runtime::metadata().encode())),
+      "chain_getBlockHash" => match block_number(params)? {
+        Some(number) => to_value(self.blocks.get(number as usize).map(|b| b.hash)),
+        None => to_value(self.best().hash),
+      },
+      "chain_getBlock" => to_value(&self.at(param(params, 0)?)?.block),
+      "chain_getHeader" => to_value(&self.at(param(params, 0)?)?.block.block.header),
+      "state_getStorage" => {
+        let key: StorageKey = param(params, 0)?.ok_or(RpcError::invalid_params("Missing storage key".into()))?;
+        let block = self.at(param(params, 1)?)?;
+        to_value(block.storage.get(&key.0).cloned().map(StorageData))
+      },
+      "state_queryStorage" => {
+        let keys: Vec<StorageKey> = param(params, 0)?.unwrap_or_default();
+        let from: Hash = param(params, 1)?.ok_or(RpcError::invalid_params("Missing block hash".into()))?;
+        to_value(self.query_storage(&keys, from, param(params, 2)?)?)
+      },
+      "state_queryStorageAt" => {
+        let keys: Vec<StorageKey> = param(params, 0)?.unwrap_or_default();
+        let block = self.at(param(params, 1)?)?;
+        let changes: Vec<(StorageKey, Option<StorageData>)> = keys.into_iter()
+          .map(|key| { let value = block.storage.get(&key.0).cloned().map(StorageData); (key, value) })
+          .collect();
+        to_value(vec![StorageChangeSet { block: block.hash, changes }])
+      },
+      "author_submitExtrinsic" => {
+        let bytes: Bytes = param(params, 0)?.ok_or(RpcError::invalid_params("Missing extrinsic".into()))?;
+        to_value(self.submit(&bytes)?)
+      },
+      _ => Err(RpcError { code: -32601, message: "Method not found".into() }),
+    }
+  }
+}
+
+/// Handle a JSON-RPC request in text, returns the response in text
+fn respond(chain: &Mutex<Chain>, request: &str) -> String {
+  let request: Value = match serde_json::from_str(request) {
+    Ok(request) => request,
+    Err(_) => return json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32700, "message": "Parse error" } }).to_string(),
+  };
+  let id = request.get("id").cloned().unwrap_or(Value::Null);
+  let method = request.get("method").and_then(|m| m.as_str()).unwrap_or("");
+  let params = request.get("params").cloned().unwrap_or(Value::Null);
+  match chain.lock().unwrap().handle(method, &params) {
+    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }).to_string(),
+    Err(err) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": err.code, "message": err.message } }).to_string(),
+  }
+}
+
+async fn serve_ws(chain: Arc<Mutex<Chain>>, stream: TcpStream) {
+  let mut ws = match async_tungstenite::accept_async(stream).await {
+    Ok(ws) => ws,
+    Err(_) => return,
+  };
+  while let Some(Ok(message)) = ws.next().await {
+    let response = match message {
+      Message::Text(text) => respond(&chain, &text),
+      Message::Close(_) => break,
+      _ => continue,
+    };
+    if ws.send(Message::Text(response)).await.is_err() {
+      break
+    }
+  }
+}
+
+/// Minimal HTTP/1.1, every request is a JSON-RPC POST and the connection is kept alive
+async fn serve_http(chain: Arc<Mutex<Chain>>, stream: TcpStream) -> Result<()> {
+  let mut reader = BufReader::new(stream.clone());
+  let mut writer = stream;
+  loop {
+    let mut content_length = 0;
+    let mut line = String::new();
+    // request line and headers
+    loop {
+      line.clear();
+      if reader.read_line(&mut line).await? == 0 {
+        return Ok(())
+      }
+      let header = line.trim_end();
+      if header.is_empty() {
+        break
+      }
+      let mut parts = header.splitn(2, ':');
+      if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+        if name.trim().eq_ignore_ascii_case("content-length") {
+          content_length = value.trim().parse().map_err(|_| "Invalid Content-Length")?;
+        }
+      }
+    }
+    if content_length > MAX_BODY_SIZE {
+      writer.write_all(b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await?;
+      return Ok(())
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await?;
+    let response = respond(&chain, &String::from_utf8_lossy(&body));
+    let head = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n", response.len());
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(response.as_bytes()).await?;
+  }
+}
+
+/// A chain with its WebSocket and HTTP listeners on localhost, port 0 lets the system choose a free port
+pub struct Server {
+  chain: Arc<Mutex<Chain>>,
+  ws: TcpListener,
+  http: TcpListener,
+}
+
+impl Server {
+  pub async fn bind(chain: Chain, ws_port: u16, http_port: u16) -> Result<Self> {
+    Ok(Self {
+      chain: Arc::new(Mutex::new(chain)),
+      ws: TcpListener::bind(("127.0.0.1", ws_port)).await?,
+      http: TcpListener::bind(("127.0.0.1", http_port)).await?,
+    })
+  }
+
+  pub fn ws_url(&self) -> Result<String> {
+    Ok(format!("ws://{}", self.ws.local_addr()?))
+  }
+
+  pub fn http_url(&self) -> Result<String> {
+    Ok(format!("http://{}", self.http.local_addr()?))
+  }
+
+  /// Serve requests until the process is stopped
+  pub async fn serve(self) -> Result<()> {
+    let Self { chain, ws, http } = self;
+    let http_chain = chain.clone();
+    task::spawn(async move {
+      let mut incoming = http.incoming();
+      while let Some(Ok(stream)) = incoming.next().await {
+        let chain = http_chain.clone();
+        task::spawn(async move {
+          let _ = serve_http(chain, stream).await;
+        });
+      }
+    });
+
+    let mut incoming = ws.incoming();
+    while let Some(stream) = incoming.next().await {
+      let stream = stream?;
+      task::spawn(serve_ws(chain.clone(), stream));
+    }
+    Ok(())
+  }
+}
+
+/// Serve `chain` over WebSocket and HTTP on localhost until the process is stopped
+pub async fn run(chain: Chain, ws_port: u16, http_port: u16) -> Result<()> {
+  let genesis_hash = chain.genesis_hash();
+  let server = Server::bind(chain, ws_port, http_port).await?;
+  println!("Genesis hash: {:#x}", genesis_hash);
+  println!("Listening on {} and {}", server.ws_url()?, server.http_url()?);
+  server.serve().await
+}
+
+/// Serve `chain` in the background on free ports, returns the WebSocket and HTTP urls
+#[cfg(test)]
+pub async fn spawn(chain: Chain) -> (String, String) {
+  let server = Server::bind(chain, 0, 0).await.unwrap();
+  let urls = (server.ws_url().unwrap(), server.http_url().unwrap());
+  task::spawn(server.serve());
+  urls
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crypto::*;
+  use crate::rpc::Rpc;
+  use sp_runtime::MultiSignature;
+
+  fn pair(seed: &str) -> ed25519::Pair {
+    ed25519::Pair::from_string(seed, None).unwrap()
+  }
+
+  fn account(pair: &ed25519::Pair) -> AccountId {
+    AccountId::from(pair.public())
+  }
+
+  fn make_transfer(chain: &Chain, from: &ed25519::Pair, to: &AccountId, value: Balance) -> Vec<u8> {
+    let nonce = chain.account_info(&account(from)).nonce;
+    let call = Call::Balances(BalancesCall::transfer(to.clone(), value));
+    transfer::make_extrinsic::<Ed25519>(call, nonce, from.clone(), chain.genesis_hash(), &RuntimeVersion::compiled()).unwrap().encode()
+  }
+
+  #[test]
+  fn test_submit_transfer() {
+    let alice = pair("//Alice");
+    let bob = account(&pair("//Bob"));
+    let mut chain = Chain::new(&[account(&alice)]);
+    let endowment = ENDOWMENT * 10u128.pow(DECIMALS);
+
+    let xt = make_transfer(&chain, &alice, &bob, 100);
+    assert!(chain.submit(&xt).is_ok());
+    assert_eq!(chain.account_info(&account(&alice)).data.free, endowment - 100);
+    assert_eq!(chain.account_info(&account(&alice)).nonce, 1);
+    assert_eq!(chain.account_info(&bob).data.free, 100);
+    assert_eq!(chain.blocks.len(), 2);
+
+    // the nonce is used
+    assert_eq!(chain.submit(&xt).unwrap_err().code, 1010);
+    // bob has not enough balance
+    assert!(chain.submit(&make_transfer(&chain, &pair("//Bob"), &account(&alice), 101)).is_err());
+
+    let changes = chain.query_storage(&[StorageKey(events_key())], chain.genesis_hash(), None).unwrap();
+    assert_eq!(changes.len(), 2);
+    let events = changes[1].changes[0].1.clone().unwrap();
+    assert!(Vec::<EventRecord<Event, Hash>>::decode(&mut &events.0[..]).unwrap().len() == 2);
+  }
+
+  #[test]
+  fn test_bad_signature() {
+    let alice = pair("//Alice");
+    let mut chain = Chain::new(&[account(&alice)]);
+    let encoded = make_transfer(&chain, &alice, &account(&pair("//Bob")), 100);
+    let mut xt = UncheckedExtrinsic::decode(&mut &encoded[..]).unwrap();
+    match xt.signature.as_mut() {
+      Some((_, MultiSignature::Ed25519(signature), _)) => signature.0[0] ^= 1,
+      _ => unreachable!(),
+    }
+    let err = chain.submit(&xt.encode()).unwrap_err();
+    assert!(err.message.contains("bad signature"), "{}", err.message);
+    assert_eq!(chain.blocks.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_rpc() {
+    let alice = pair("//Alice");
+    let chain = Chain::new(&[account(&alice)]);
+    let genesis_hash = chain.genesis_hash();
+    let (ws_url, http_url) = spawn(chain).await;
+
+    for url in vec![ws_url, http_url] {
+      let rpc = Rpc::new(url).await.unwrap();
+      assert_eq!(rpc.genesis_hash().await.unwrap(), genesis_hash);
+      let balances = rpc.get_balances(vec![account(&alice)]).await.unwrap();
+      assert!(balances[0].1 > 0);
+      assert!(rpc.block(None).await.unwrap().is_some());
+    }
+  }
+
+  #[tokio::test]
+  async fn test_transfer_through_rpc() {
+    let (alice, bob) = (pair("//Alice"), account(&pair("//Bob")));
+    let chain = Chain::new(&[account(&alice)]);
+    let genesis_hash = chain.genesis_hash();
+    let (ws_url, _) = spawn(chain).await;
+    let rpc = Rpc::new(ws_url).await.unwrap();
+
+    let version = rpc.runtime_version(None).await.unwrap();
+    let nonce = rpc.get_account_info(account(&alice)).await.unwrap().nonce;
+    let call = Call::Balances(BalancesCall::transfer(bob.clone(), 100));
+    let xt = transfer::make_extrinsic::<Ed25519>(call, nonce, alice.clone(), genesis_hash, &version).unwrap();
+    let hash = rpc.submit_extrinsic(xt.clone()).await.unwrap();
+    assert_eq!(hash, H256::from(blake2_256(&xt.encode())));
+
+    let free = rpc.get_balances(vec![account(&alice), bob.clone()]).await.unwrap();
+    assert_eq!(free[0].1, ENDOWMENT * 10u128.pow(DECIMALS) - 100);
+    assert_eq!(free[1].1, 100);
+    assert_eq!(rpc.get_account_info(account(&alice)).await.unwrap().nonce, nonce + 1);
+
+    let events = rpc.get_storage(StorageKey(events_key()), None).await.unwrap().unwrap();
+    let events = Vec::<EventRecord<Event, Hash>>::decode(&mut &events.0[..]).unwrap();
+    match &events[0].event {
+      Event::balances(balances::RawEvent::Transfer(from, to, value)) => {
+        assert_eq!((from, to, *value), (&account(&alice), &bob, 100));
+      },
+      _ => unreachable!(),
+    }
+    // the nonce is used, the error of the node is returned
+    assert!(rpc.submit_extrinsic(xt).await.unwrap_err().to_string().contains("Invalid Transaction"));
+  }
+}
@@ -31,6 +31,19 @@ pub enum Error {
   #[error("Serialize toml error: {0}")]
   SerializeToml(#[from] toml::ser::Error),
 
+  #[error("Sqlite error: {0}")]
+  Sqlite(#[from] rusqlite::Error),
+
+  /// Url which is neither websocket nor http
+  #[error("Invalid rpc url `{0}`, it should start with ws://, wss://, http:// or https://")]
+  InvalidUrl(String),
+  /// No response in the time of `[connection] timeout`
+  #[error("Rpc timed out: {0}")]
+  Timeout(String),
+  /// None of the endpoints can be connected or is in sync
+  #[error("No rpc endpoint is available:\n{0}")]
+  Unavailable(String),
+
   #[error("Invalid SS58 address")]
   PublicKey(PublicError),
   
@@ -39,6 +52,16 @@ pub enum Error {
   Other(String),
 }
 
+impl Error {
+  /// The request may succeed on another endpoint or later, other errors are answered by the node or made locally
+  pub fn is_transport(&self) -> bool {
+    match self {
+      Error::Rpc(RequestError::TransportError(_)) | Error::WsHandshake(_) | Error::Timeout(_) | Error::Unavailable(_) => true,
+      _ => false,
+    }
+  }
+}
+
 impl From<PublicError> for Error {
   fn from(error: PublicError) -> Self {
     Error::PublicKey(error)
@@ -1,16 +1,68 @@
 use std::fs;
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
+use zeroize::Zeroizing;
 use crate::crypto::*;
 use crate::pkcs8;
 
+/// Version 2 keystore uses a single string, version 3 uses a list, e.g. `["scrypt", "xsalsa20-poly1305"]`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum EncodingType {
+  Single(String),
+  Multiple(Vec<String>),
+}
+
+impl EncodingType {
+  pub fn contains(&self, name: &str) -> bool {
+    match self {
+      EncodingType::Single(v) => v.as_str() == name,
+      EncodingType::Multiple(v) => v.iter().any(|t| t.as_str() == name),
+    }
+  }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Encoding {
   pub content: Vec<String>,
-  pub r#type: String,
+  pub r#type: EncodingType,
   pub version: String,
 }
 
+impl Encoding {
+  pub fn v2(crypto_type: &str) -> Self {
+    Self {
+      content: vec!["pkcs8".to_owned(), crypto_type.to_owned()],
+      r#type: EncodingType::Single("xsalsa20-poly1305".to_owned()),
+      version: "2".to_owned(),
+    }
+  }
+
+  pub fn v3(crypto_type: &str, encrypted: bool) -> Self {
+    Self {
+      content: vec!["pkcs8".to_owned(), crypto_type.to_owned()],
+      r#type: Self::v3_type(encrypted),
+      version: "3".to_owned(),
+    }
+  }
+
+  pub fn batch(encrypted: bool) -> Self {
+    Self {
+      content: vec!["batch-pkcs8".to_owned()],
+      r#type: Self::v3_type(encrypted),
+      version: "3".to_owned(),
+    }
+  }
+
+  fn v3_type(encrypted: bool) -> EncodingType {
+    if encrypted {
+      EncodingType::Multiple(vec!["scrypt".to_owned(), "xsalsa20-poly1305".to_owned()])
+    } else {
+      EncodingType::Multiple(vec!["none".to_owned()])
+    }
+  }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Keystore {
   pub address: String,
@@ -43,25 +95,43 @@ impl Keystore {
     self.meta["whenCreated"].as_u64().unwrap_or(0u64)
   }
 
+  pub fn is_scrypt(&self) -> bool {
+    self.encoding.version == "3" && self.encoding.r#type.contains("scrypt")
+  }
+
+  pub fn is_encrypted(&self) -> bool {
+    self.encoding.r#type.contains("xsalsa20-poly1305")
+  }
+
   pub fn to_json(&self) -> String {
     serde_json::to_string(&self).unwrap()
   }
 
-  pub fn encoded_bytes(&self) -> Vec<u8> {
-    let encoded = if self.encoded.starts_with("0x") {
-      &self.encoded[2..]
+  /// Version 2 keystore is hex encoded, version 3 keystore is base64 encoded,
+  /// the bytes are wiped on drop since an unencrypted keystore holds the plain secret key
+  pub fn encoded_bytes(&self) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(if self.encoded.starts_with("0x") {
+      hex::decode(&self.encoded[2..]).unwrap_or(vec![])
+    } else if self.encoding.version == "3" {
+      base64::decode(&self.encoded).unwrap_or(vec![])
     } else {
-      &self.encoded
-    };
-    hex::decode(encoded).unwrap_or(vec![])
+      hex::decode(&self.encoded).unwrap_or(vec![])
+    })
   }
 
-  pub fn into_pair<T: Crypto>(&self, password: Option<String>) -> Result<T::Pair, ()> {
+  pub fn into_pair<T: Crypto>(&self, password: Option<&str>) -> Result<T::Pair, ()> {
     let encoded = self.encoded_bytes();
     if encoded.is_empty() {
       return Err(())
     }
-    match pkcs8::decode(&encoded[..], password) {
+    let decoded = if !self.is_encrypted() {
+      pkcs8::decode(&encoded[..], None)
+    } else if self.is_scrypt() {
+      pkcs8::decode_scrypt(&encoded[..], password)
+    } else {
+      pkcs8::decode(&encoded[..], password)
+    };
+    match decoded {
       Ok((_, secret_key)) => {
         T::pair_from_secret_slice(&secret_key[..])
       },
@@ -70,6 +140,66 @@ impl Keystore {
   }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchAccount {
+  pub address: String,
+  pub meta: Value,
+}
+
+/// The json file of polkadot-js "Export all accounts", `encoded` is an encrypted json array of keystores
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchKeystore {
+  pub encoded: String,
+  pub encoding: Encoding,
+  pub accounts: Vec<BatchAccount>,
+}
+
+impl BatchKeystore {
+  pub fn parse_from_file(path: String) -> Result<Self, ()> {
+    let data = fs::read_to_string(path).map_err(|_| () )?;
+    let batch: Self = serde_json::from_str(&data).map_err( |_| () )?;
+    if !batch.encoding.content.iter().any(|c| c.as_str() == "batch-pkcs8") {
+      return Err(())
+    }
+    Ok(batch)
+  }
+
+  pub fn from_keystores(keystores: Vec<Keystore>, password: Option<&str>) -> Result<Self, ()> {
+    let accounts = keystores.iter().map(|keystore| {
+      BatchAccount {
+        address: keystore.address.clone(),
+        meta: keystore.meta.clone(),
+      }
+    }).collect();
+
+    let data = serde_json::to_vec(&keystores).map_err(|_| () )?;
+    let (encoded, encrypted) = match password {
+      Some(password) if !password.is_empty() => (pkcs8::encrypt_scrypt(&data[..], password)?, true),
+      _ => (data, false),
+    };
+
+    Ok(Self {
+      encoded: base64::encode(encoded),
+      encoding: Encoding::batch(encrypted),
+      accounts: accounts,
+    })
+  }
+
+  pub fn into_keystores(&self, password: Option<&str>) -> Result<Vec<Keystore>, ()> {
+    let encoded = base64::decode(&self.encoded).map_err(|_| () )?;
+    let data = if self.encoding.r#type.contains("xsalsa20-poly1305") {
+      pkcs8::decrypt_scrypt(&encoded[..], password.ok_or(())?)?
+    } else {
+      Zeroizing::new(encoded)
+    };
+    serde_json::from_slice(&data[..]).map_err(|_| () )
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(&self).unwrap()
+  }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -103,7 +233,7 @@ mod tests {
     let expect_pair = ecdsa::Pair::from_seed(&seed);
     
     let keystore = Keystore::parse_from_file("tests/fixtures/ecdsa.json".into()).unwrap();
-    let password = Some("111111".to_string());
+    let password = Some("111111");
     let pair = keystore.into_pair::<Ecdsa>(password).unwrap();
     assert_eq!(pair.to_raw_vec(), expect_pair.to_raw_vec());
     assert_eq!(pair.public(), expect_pair.public());
@@ -115,7 +245,7 @@ mod tests {
     let expect_pair = ed25519::Pair::from_seed(&seed);
 
     let keystore = Keystore::parse_from_file("tests/fixtures/ed25519.json".into()).unwrap();
-    let password = Some("111111".to_string());
+    let password = Some("111111");
     let pair = keystore.into_pair::<Ed25519>(password).unwrap();
 
     assert_eq!(pair.to_raw_vec(), expect_pair.to_raw_vec());
@@ -128,13 +258,94 @@ mod tests {
     let expect_pair = sr25519::Pair::from_seed(&seed);
 
     let keystore = Keystore::parse_from_file("tests/fixtures/sr25519.json".into()).unwrap();
-    let password = Some("111111".to_string());
+    let password = Some("111111");
     let pair = keystore.into_pair::<Sr25519>(password).unwrap();
 
     assert_eq!(pair.to_raw_vec(), expect_pair.to_raw_vec());
     assert_eq!(pair.public(), expect_pair.public());
   }
 
+  #[test]
+  fn test_parse_v3_json_file() {
+    let keystore = Keystore::parse_from_file("tests/fixtures/sr25519_v3.json".into()).unwrap();
+    assert!(keystore.is_scrypt());
+    assert!(keystore.is_encrypted());
+    assert_eq!(keystore.crypto(), "sr25519");
+  }
+
+  #[test]
+  fn test_into_pair_for_ecdsa_v3() {
+    let seed = hex!("bda7ce4ab5c0bdcfbf3f5353adb1ae795aa793261dd478c26cb97735b68bc687");
+    let expect_pair = ecdsa::Pair::from_seed(&seed);
+
+    let keystore = Keystore::parse_from_file("tests/fixtures/ecdsa_v3.json".into()).unwrap();
+    let password = Some("111111");
+    let pair = keystore.into_pair::<Ecdsa>(password).unwrap();
+    assert_eq!(pair.public(), expect_pair.public());
+  }
+
+  #[test]
+  fn test_into_pair_for_ed25519_v3() {
+    let seed = hex!("bda7ce4ab5c0bdcfbf3f5353adb1ae795aa793261dd478c26cb97735b68bc687");
+    let expect_pair = ed25519::Pair::from_seed(&seed);
+
+    let keystore = Keystore::parse_from_file("tests/fixtures/ed25519_v3.json".into()).unwrap();
+    let password = Some("111111");
+    let pair = keystore.into_pair::<Ed25519>(password).unwrap();
+    assert_eq!(pair.public(), expect_pair.public());
+  }
+
+  #[test]
+  fn test_into_pair_for_sr25519_v3() {
+    let seed = hex!("bda7ce4ab5c0bdcfbf3f5353adb1ae795aa793261dd478c26cb97735b68bc687");
+    let expect_pair = sr25519::Pair::from_seed(&seed);
+
+    let keystore = Keystore::parse_from_file("tests/fixtures/sr25519_v3.json".into()).unwrap();
+    let password = Some("111111");
+    let pair = keystore.into_pair::<Sr25519>(password).unwrap();
+    assert_eq!(pair.public(), expect_pair.public());
+  }
+
+  #[test]
+  fn test_parse_batch_json_file() {
+    assert!(BatchKeystore::parse_from_file("tests/fixtures/sr25519_v3.json".into()).is_err());
+
+    let batch = BatchKeystore::parse_from_file("tests/fixtures/batch.json".into()).unwrap();
+    assert_eq!(batch.accounts.len(), 3);
+    assert_eq!(batch.accounts[0].address, "13SmLJEpENqt1mdZsFjhq8BgYYTBPAgPxrjaad4yNd4Bgw7Y");
+  }
+
+  #[test]
+  fn test_batch_into_keystores() {
+    let batch = BatchKeystore::parse_from_file("tests/fixtures/batch.json".into()).unwrap();
+    assert!(batch.into_keystores(Some("incorrect")).is_err());
+
+    let keystores = batch.into_keystores(Some("111111")).unwrap();
+    assert_eq!(keystores.len(), 3);
+    assert_eq!(keystores[2].label(), "sr25519");
+    assert_eq!(keystores[2].when_created(), 1591600865993u64);
+    let pair = keystores[2].into_pair::<Sr25519>(Some("111111")).unwrap();
+    assert_eq!(Sr25519::to_address(&pair), keystores[2].address);
+  }
+
+  #[test]
+  fn test_batch_from_keystores() {
+    let keystore = Keystore::parse_from_file("tests/fixtures/ed25519.json".into()).unwrap();
+    let password = Some("222222");
+    let batch = BatchKeystore::from_keystores(vec![keystore.clone()], password).unwrap();
+    assert_eq!(batch.encoding.content, vec!["batch-pkcs8".to_string()]);
+    assert_eq!(batch.accounts[0].address, keystore.address);
+
+    let keystores = batch.into_keystores(password).unwrap();
+    assert_eq!(keystores[0].encoded, keystore.encoded);
+  }
+
+  #[test]
+  fn test_into_pair_v3_with_incorrect_password() {
+    let keystore = Keystore::parse_from_file("tests/fixtures/sr25519_v3.json".into()).unwrap();
+    let password = Some("incorrect");
+    assert!(keystore.into_pair::<Sr25519>(password).is_err());
+  }
 }
 
 
@@ -11,74 +11,628 @@ mod rpc;
 mod store;
 mod transfer;
 mod primitives;
-// mod sync;
+mod agent;
+mod migration;
+mod backend;
+mod replay;
+mod devnode;
+mod shamir;
+mod vanity;
+mod sync;
 
 
 use sp_core::crypto::{Ss58AddressFormat, set_default_ss58_version };
 use std::path::{ Path, PathBuf };
+use std::convert::TryFrom;
 use std::fs;
-use crate::primitives::{ AccountId, AccountInfo };
+use std::env;
+use std::io::{ self, Write };
+use clap::ArgMatches;
+use std::time::SystemTime;
+use crate::primitives::{ AccountId, AccountInfo, Hash, Nonce };
 use runtime::{ BalancesCall, Call };
 use rust_decimal::prelude::*;
 use std::ops::{Mul, Div};
+use codec::Encode;
+use zeroize::Zeroizing;
 
-use keystore::Keystore;
+use keystore::{ Keystore, BatchKeystore };
+use shamir::{ Share, ShareFile };
 use crypto::*;
 use wallet::*;
 use rpc::*;
-use networks::Network;
+use networks::{ Network, Networks, DEFAULT_NETWORK, LEGACY_UNKNOWN_NETWORK };
 use store::*;
 
-fn default_path() -> PathBuf {
-  let mut path = dirs::home_dir().unwrap();
-  path.push(".subwallet");
+/// Environment variable of the data directory, `--datadir` overrides it
+const HOME_ENV: &str = "SUBWALLET_HOME";
+/// Directory of profiles in the data directory
+const PROFILES_DIR: &str = "profiles";
+
+/// `--datadir`, then `$SUBWALLET_HOME`, then `~/.subwallet`. A profile is an isolated data path in `profiles` of it
+fn resolve_data_path(datadir: Option<&str>, profile: Option<&str>) -> std::result::Result<PathBuf, Box<dyn std::error::Error>> {
+  let home = env::var(HOME_ENV).ok().filter(|home| !home.is_empty());
+  let mut path = match datadir.map(String::from).or(home) {
+    Some(dir) => PathBuf::from(dir),
+    None => {
+      let mut path = dirs::home_dir().ok_or("Failed to find home directory, use `--datadir` instead")?;
+      path.push(".subwallet");
+      path
+    },
+  };
+  if let Some(profile) = profile {
+    if !WalletStore::is_valid_name(profile) {
+      return Err(format!("Invalid profile name `{}`", profile).into())
+    }
+    path.push(PROFILES_DIR);
+    path.push(profile);
+  }
   if !path.exists() {
-    fs::create_dir_all(path.clone()).expect("Failed to create default data path");
+    fs::create_dir_all(&path)?;
+  }
+  Ok(path)
+}
+
+/// The network `name` in the registry
+fn get_network(config: &rpc::Config, name: &str) -> std::result::Result<Network, Box<dyn std::error::Error>> {
+  let network = config.networks().get(name).cloned()
+    .ok_or(format!("Unknown network `{}`, add it to `[[network]]` of config.toml", name))?;
+  Ok(network)
+}
+
+/// Parse an address of `network`, addresses of other networks are rejected
+fn parse_account(addr: &str, network: &Network) -> std::result::Result<AccountId, Box<dyn std::error::Error>> {
+  let (account_id, format) = AccountId::from_ss58check_with_version(addr).map_err(|_err| format!("Invalid address `{}`", addr))?;
+  if u8::from(format) != network.ss58_format {
+    let err = format!("`{}` is not a {} address", addr, network.name);
+    return Err(err.into())
+  }
+  Ok(account_id)
+}
+
+/// Addresses saved before networks are recorded, or recorded as the legacy unknown network, belong to any network
+fn check_network(address: &Address, network: &Network) -> std::result::Result<(), Box<dyn std::error::Error>> {
+  let recorded = address.network.as_str();
+  if !recorded.is_empty() && recorded != LEGACY_UNKNOWN_NETWORK && recorded != network.name {
+    let err = format!("`{}` belongs to {}, use `--network {}`", address.addr, address.network, address.network);
+    return Err(err.into())
+  }
+  Ok(())
+}
+
+/// Connect to the endpoints of network, or replay the responses recorded by `--rpc-record` if `--rpc-replay` is given
+async fn connect(matches: &ArgMatches<'_>, config: &rpc::Config, network: &Network) -> std::result::Result<Rpc, Box<dyn std::error::Error>> {
+  if let Some(file) = global_value(matches, "rpc-replay") {
+    return Ok(Rpc::replay(Path::new(file))?)
+  }
+  let rpc = Rpc::for_network(network, config.get_urls(network), config.retry_policy()).await?;
+  match global_value(matches, "rpc-record") {
+    Some(file) => Ok(rpc.record(Path::new(file))),
+    None => Ok(rpc),
+  }
+}
+
+/// Decimals and symbol of the token reported by the node, those of the network are used if they are not reported
+async fn token(rpc: &Rpc, network: &Network) -> std::result::Result<(u32, String), Box<dyn std::error::Error>> {
+  let properties = rpc.system_properties().await?;
+  let decimals = properties.get("tokenDecimals").and_then(|v| v.as_u64()).map_or(network.decimals, |v| v as u32);
+  let symbol = properties.get("tokenSymbol").and_then(|v| v.as_str()).unwrap_or(&network.symbol).to_string();
+  Ok((decimals, symbol))
+}
+
+/// Free balances of `accounts` in tokens, and the symbol of the token
+async fn get_balances(rpc: &Rpc, network: &Network, accounts: Vec<AccountId>) -> std::result::Result<(Vec<(AccountId, Decimal)>, String), Box<dyn std::error::Error>> {
+  let (decimals, symbol) = token(rpc, network).await?;
+  let divider: Decimal = 10u64.saturating_pow(decimals).into();
+  let balances = rpc.get_balances(accounts).await?.into_iter()
+    .map(|(account, balance)| (account, Decimal::from_str(&balance.to_string()).unwrap().div(divider)))
+    .collect();
+  Ok((balances, symbol))
+}
+
+/// A transfer call and what it is signed with, they are read from the node
+struct PreparedTransfer {
+  call: Call,
+  nonce: Nonce,
+  genesis_hash: Hash,
+  version: RuntimeVersion,
+}
+
+/// Check the node serves `network`, then make the call to transfer `value` tokens from `from` to `to`
+async fn prepare_transfer(rpc: &Rpc, network: &Network, from: &AccountId, to: AccountId, value: Decimal) -> std::result::Result<PreparedTransfer, Box<dyn std::error::Error>> {
+  // refuse to sign for a chain other than the selected network
+  network.verify(&rpc.chain_identity().await?)?;
+  let genesis_hash = match network.genesis_hash_bytes() {
+    Some(hash) => Hash::from_slice(&hash),
+    None => rpc.genesis_hash().await?,
+  };
+  // the payload is signed with the runtime version of the node, so it keeps working after runtime upgrades
+  let version = rpc.runtime_version(None).await?;
+  if let Some(warning) = transfer::check_call_encoding(&rpc.metadata_bytes(None).await?, "Balances", "transfer") {
+    eprintln!("Warning: {}", warning);
+  }
+  let info: AccountInfo = rpc.get_account_info(from.clone()).await?;
+  let (decimals, _) = token(rpc, network).await?;
+  let multipler: Decimal = 10u64.saturating_pow(decimals).into();
+  let amount = value.mul(multipler).to_u128().ok_or("Invalid `amount`")?;
+  Ok(PreparedTransfer {
+    call: Call::Balances(BalancesCall::transfer(to, amount)),
+    nonce: info.nonce,
+    genesis_hash,
+    version,
+  })
+}
+
+/// Global args may be given before or after the subcommand
+fn global_value<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+  matches.value_of(name).or_else(|| matches.subcommand().1.and_then(|m| m.value_of(name)))
+}
+
+/// Read password from tty, it is wiped on drop
+fn read_password(prompt: &str) -> std::io::Result<Zeroizing<String>> {
+  rpassword::read_password_from_tty(Some(prompt)).map(Zeroizing::new)
+}
+
+/// Ask for the wallet passphrase if the wallet is encrypted
+fn unlock_wallet(store: &WalletStore) -> std::result::Result<(), Box<dyn std::error::Error>> {
+  if store.is_encrypted() && store.is_locked() {
+    let passphrase = read_password("Wallet passphrase: ")?;
+    store.unlock(&passphrase)?;
+  }
+  Ok(())
+}
+
+/// Save address of `network` to wallet, the seed is encrypted by unlock agent if it is running
+fn save_address(store: &WalletStore, network: &Network, address: Address) -> std::result::Result<(), Box<dyn std::error::Error>> {
+  let mut address = address;
+  address.network = network.name.clone();
+  if store.is_encrypted() && store.is_locked() && !address.is_watchonly() {
+    if let Some(agent) = agent::Client::connect(store.db_path(), store.name()) {
+      let sealed = agent.seal(&address.seed[..])?;
+      address.set_seed(sealed);
+      store.save_sealed(address)?;
+      return Ok(())
+    }
+    unlock_wallet(store)?;
+  }
+  store.save(address)?;
+  Ok(())
+}
+
+fn prompt(msg: &str) -> std::io::Result<String> {
+  print!("{}", msg);
+  io::stdout().flush()?;
+  let mut input = String::new();
+  io::stdin().read_line(&mut input)?;
+  Ok(input.trim().to_string())
+}
+
+/// Ask for some random words of the phrase to make sure it is written down
+fn confirm_phrase(phrase: &str) -> std::io::Result<bool> {
+  let words: Vec<&str> = phrase.split_whitespace().collect();
+  let mut positions = rand::seq::index::sample(&mut rand::thread_rng(), words.len(), 3).into_vec();
+  positions.sort();
+  for i in positions {
+    let word = prompt(format!("Word #{}: ", i + 1).as_str())?;
+    if word != words[i] {
+      return Ok(false)
+    }
+  }
+  Ok(true)
+}
+
+/// Read the backup file back, the addresses are marked as backed up if all of them can be restored
+fn verify_backup(store: &WalletStore, networks: &Networks, path: &Path, addresses: &[Address], password: Option<&str>) -> std::result::Result<(), Box<dyn std::error::Error>> {
+  let file = path.to_str().unwrap().to_string();
+  let keystores = match BatchKeystore::parse_from_file(file.clone()) {
+    Ok(batch) => batch.into_keystores(password).map_err(|_| "Failed to decrypt the backup file")?,
+    Err(_) => vec![Keystore::parse_from_file(file).map_err(|_| "Failed to parse the backup file")?],
+  };
+  let mut restored = vec![];
+  for keystore in keystores {
+    let address = Address::from_keystore(keystore.clone(), password, networks)
+      .map_err(|_| format!("Failed to restore `{}` from the backup file", keystore.address))?;
+    restored.push(address.addr);
+  }
+  if !addresses.iter().all(|address| restored.contains(&address.addr)) {
+    return Err("Failed to verify the backup file, please check it and backup again".into())
+  }
+  for address in addresses {
+    store.set_backed_up(&address.addr)?;
+  }
+  Ok(())
+}
+
+/// Rebuild address from seed, every cryptography is tried if `crypto_type` is unknown
+fn recover_address(seed: &[u8], crypto_type: Option<&str>, path: &str, expected: &str) -> Option<Address> {
+  let expected_id = AccountId::from_ss58check(expected).ok()?;
+  for crypto in ["sr25519", "ed25519", "ecdsa"].iter().filter(|c| crypto_type.map_or(true, |t| t == **c)) {
+    let result = match *crypto {
+      "ed25519" => Address::from_seed_with_path::<Ed25519>(seed, path),
+      "ecdsa" => Address::from_seed_with_path::<Ecdsa>(seed, path),
+      _ => Address::from_seed_with_path::<Sr25519>(seed, path),
+    };
+    match result {
+      Ok(address) if AccountId::from_ss58check(&address.addr).ok() == Some(expected_id.clone()) => return Some(address),
+      _ => {},
+    }
+  }
+  None
+}
+
+/// Append `file_name` if `path` is a directory
+fn backup_path(path: &str, file_name: String) -> PathBuf {
+  let path = Path::new(path);
+  if path.ends_with("/") || path.is_dir() { // dir
+    let mut path = path.to_path_buf();
+    path.push(file_name);
+    path
+  } else { // file
+    path.to_path_buf()
   }
-  path
 }
 
 #[async_std::main]
-async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+async fn main() {
+  // errors are shown by their messages rather than debug output
+  if let Err(err) = run().await {
+    eprintln!("Error: {}", err);
+    std::process::exit(1);
+  }
+}
+
+async fn run() -> std::result::Result<(), Box<dyn std::error::Error>> {
   let mut app = command::get_app();
   let matches = app.clone().get_matches();
-  set_default_ss58_version(Ss58AddressFormat::PolkadotAccount);
 
-  let data_path = default_path();
+  let data_path = resolve_data_path(global_value(&matches, "datadir"), global_value(&matches, "profile"))?;
   let config_file = data_path.join("config.toml");
-  let store = WalletStore::init(data_path.as_path().to_str());
 
   if !config_file.exists() {
     let mut config = rpc::Config::new();
-    config.set_url(Network::Polkadot, "wss://rpc.polkadot.io".to_string());
+    config.set_urls(DEFAULT_NETWORK, vec!["wss://rpc.polkadot.io".to_string()]);
     let _ = config.write_to_file(config_file.as_path());
   }
+  let config = rpc::Config::parse_from_file(config_file.as_path()).unwrap_or_else(|_| rpc::Config::new());
+
+  let network_name = global_value(&matches, "network")
+    .map(|name| name.to_string())
+    .or_else(|| config.default_network())
+    .unwrap_or(DEFAULT_NETWORK.to_string());
+  let networks = config.networks();
+  let network = get_network(&config, &network_name)?;
+  // addresses are shown and parsed in the format of the network
+  let format = Ss58AddressFormat::try_from(network.ss58_format).unwrap_or(Ss58AddressFormat::Custom(network.ss58_format));
+  set_default_ss58_version(format);
+
+  let mut wallet_name = global_value(&matches, "wallet")
+    .map(|name| name.to_string())
+    .or_else(|| config.default_wallet())
+    .unwrap_or(DEFAULT_WALLET_NAME.to_string());
+  if !WalletStore::is_valid_name(&wallet_name) {
+    return Err(format!("Invalid wallet name `{}`", wallet_name).into())
+  }
+  if !WalletStore::exists(data_path.to_str(), &wallet_name) && wallet_name != DEFAULT_WALLET_NAME {
+    match matches.subcommand_name() {
+      Some("createwallet") | Some("listwallets") | Some("loadwallet") => wallet_name = DEFAULT_WALLET_NAME.to_string(),
+      _ => {
+        let err = format!("Wallet `{}` does not exist, use `createwallet` to create it", wallet_name);
+        return Err(err.into())
+      }
+    }
+  }
+  let store = WalletStore::open(data_path.to_str(), &wallet_name);
 
   match matches.subcommand() {
     ("getnewaddress", Some(matches)) => {
       let label = matches.value_of("label").unwrap();
+      store.check_label(label)?;
+      let path = DerivationPath::parse(matches.value_of("path").unwrap_or("")).map_err(|_| "Invalid derivation path")?;
+      let result = if matches.is_present("ed25519") {
+        Address::generate_with_path::<Ed25519>(&path)
+      } else if matches.is_present("ecdsa") {
+        Address::generate_with_path::<Ecdsa>(&path)
+      } else {
+        Address::generate_with_path::<Sr25519>(&path)
+      };
+      let (mut address, phrase) = result.map_err(|_| "The derivation path is not supported by the cryptography")?;
+
+      if !matches.is_present("no-phrase") {
+        println!("Write down the mnemonic phrase, it will not be shown again without password:\n");
+        println!("  {}\n", phrase.as_str());
+        prompt("Press enter when you have written it down")?;
+        // clear the screen so the phrase does not stay in terminal
+        print!("\x1B[2J\x1B[1;1H");
+
+        if !confirm_phrase(&phrase)? {
+          return Err("Mnemonic phrase confirmation failed, the address is not saved".into())
+        }
+
+        let password = read_password("Type password to encrypt phrase: ")?;
+        let password_confirmation = read_password("Type password again: ")?;
+        if password != password_confirmation {
+          return Err("Two passwords are inconsistent".into())
+        }
+        address.encrypt_phrase(&phrase, &password).map_err(|_| "Failed to encrypt mnemonic phrase, password can not be empty")?;
+      }
+
+      address.label = label.to_string();
+      save_address(&store, &network, address.clone())?;
+      println!("{}", address.addr);
+    }
+    ("showphrase", Some(matches)) => {
+      let label = matches.value_of("label").unwrap();
+      let address = store.read(label).ok_or("The label or address does not exists")?;
+      if !address.has_phrase() {
+        let err = format!("Mnemonic phrase of `{}` is not kept", label);
+        return Err(err.into());
+      }
+      let password = read_password("Password: ")?;
+      let phrase = address.decrypt_phrase(&password).map_err(|_| "Incorrect password")?;
+      println!("{}", phrase.as_str());
+      if !address.path.is_empty() {
+        println!("Derivation path: {}", address.path);
+      }
+    }
+    ("getvanityaddress", Some(matches)) => {
+      let label = matches.value_of("label").unwrap();
+      store.check_label(label)?;
+      let pattern = vanity::Pattern::new(matches.value_of("prefix"), matches.value_of("suffix"), matches.value_of("contains"), format)?;
+      let threads = num_cpus::get();
       let mut address = if matches.is_present("ed25519") {
-        Address::generate::<Ed25519>()
+        vanity::generate::<Ed25519>(pattern, threads)?
+      } else if matches.is_present("ecdsa") {
+        vanity::generate::<Ecdsa>(pattern, threads)?
+      } else {
+        vanity::generate::<Sr25519>(pattern, threads)?
+      };
+      address.label = label.to_string();
+      save_address(&store, &network, address.clone())?;
+      println!("{}", address.addr);
+    }
+    ("derive", Some(matches)) => {
+      let parent = matches.value_of("parent").unwrap();
+      let label = matches.value_of("label").unwrap();
+      store.check_label(label)?;
+      let path = DerivationPath::parse(matches.value_of("path").unwrap()).map_err(|_| "Invalid derivation path")?;
+      if path.password.is_some() {
+        return Err("Password `///` can only be used with mnemonic phrase".into())
+      }
+
+      unlock_wallet(&store)?;
+      let parent_address = store.read(parent).ok_or("The parent label or address does not exists")?;
+      if parent_address.is_watchonly() {
+        let err = format!("Watchonly address `{}` can not be derived", parent_address.label);
+        return Err(err.into());
+      }
+      check_network(&parent_address, &network)?;
+
+      let mut address = parent_address.derive(&path.path).map_err(|_| "The derivation path is not supported by the cryptography")?;
+      if store.read(&address.addr).is_some() {
+        println!("`{}` already exists", address.addr);
+        return Ok(())
+      }
+      address.label = label.to_string();
+      address.network = network.name.clone();
+      store.save(address.clone())?;
+      println!("{}", address.addr);
+    }
+    ("importphrase", Some(matches)) => {
+      let label = matches.value_of("label").unwrap();
+      store.check_label(label)?;
+      let phrase = read_password("Phrase: ")?;
+      let phrase = Zeroizing::new(phrase.split_whitespace().collect::<Vec<&str>>().join(" "));
+
+      let path = DerivationPath::parse(matches.value_of("path").unwrap_or("")).map_err(|_| "Invalid derivation path")?;
+
+      let result = if matches.is_present("ed25519") {
+        Address::from_phrase_with_path::<Ed25519>(&phrase, &path)
       } else if matches.is_present("ecdsa") {
-        Address::generate::<Ecdsa>()
+        Address::from_phrase_with_path::<Ecdsa>(&phrase, &path)
       } else {
-        Address::generate::<Sr25519>()
+        Address::from_phrase_with_path::<Sr25519>(&phrase, &path)
       };
+      let mut address = result.map_err(|_| "Invalid mnemonic phrase or derivation path")?;
+
+      if let Some(expected) = matches.value_of("address") {
+        let expected_id = AccountId::from_ss58check(expected).map_err(|_err| "Invalid address" )?;
+        let actual_id = AccountId::from_ss58check(&address.addr).map_err(error::Error::PublicKey)?;
+        if expected_id != actual_id {
+          let err = format!("Derived address `{}` does not match `{}`", address.addr, expected);
+          return Err(err.into());
+        }
+      }
+
+      if store.read(&address.addr).is_some() {
+        println!("`{}` already exists", address.addr);
+        return Ok(())
+      }
 
       address.label = label.to_string();
-      store.save(address.clone());
+      save_address(&store, &network, address.clone())?;
       println!("{}", address.addr);
     }
-    ("listaddresses", Some(_)) => {
+    ("createwallet", Some(matches)) => {
+      let name = matches.value_of("name").unwrap();
+      WalletStore::create(data_path.to_str(), name)?;
+      println!("Wallet `{}` is created", name);
+    }
+    ("listwallets", Some(_)) => {
+      for name in WalletStore::list(data_path.to_str()) {
+        if name == store.name() {
+          println!("* {}", name);
+        } else {
+          println!("  {}", name);
+        }
+      }
+    }
+    ("loadwallet", Some(matches)) => {
+      let name = matches.value_of("name").unwrap();
+      if !WalletStore::exists(data_path.to_str(), name) && name != DEFAULT_WALLET_NAME {
+        let err = format!("Wallet `{}` does not exist, use `createwallet` to create it", name);
+        return Err(err.into())
+      }
+      let mut config = config;
+      config.set_default_wallet(name.to_string());
+      config.write_to_file(config_file.as_path())?;
+      println!("Wallet `{}` is loaded as default wallet", name);
+    }
+    ("devnode", Some(matches)) => {
+      let ws_port = matches.value_of("port").unwrap_or("9944").parse::<u16>().map_err(|_| "Invalid `port`")?;
+      let http_port = matches.value_of("http-port").unwrap_or("9933").parse::<u16>().map_err(|_| "Invalid `http-port`")?;
+      let mut endowed: Vec<AccountId> = ["//Alice", "//Bob"].iter()
+        .map(|seed| AccountId::from(sr25519::Pair::from_string(seed, None).unwrap().public()))
+        .collect();
+      if let Some(addrs) = matches.values_of("endow") {
+        for addr in addrs {
+          endowed.push(AccountId::from_ss58check(addr).map_err(|_| format!("Invalid address `{}`", addr))?);
+        }
+      }
+      devnode::run(devnode::Chain::new(&endowed), ws_port, http_port).await?;
+    }
+    ("setnetwork", Some(matches)) => {
+      let name = matches.value_of("name").unwrap();
+      get_network(&config, name)?;
+      let mut config = config;
+      config.set_default_network(name.to_string());
+      config.write_to_file(config_file.as_path())?;
+      println!("`{}` is set as default network", name);
+    }
+    ("encryptwallet", Some(_)) => {
+      if store.is_encrypted() {
+        return Err("Wallet is already encrypted, use `changepassphrase` to change the passphrase".into())
+      }
+      let passphrase = read_password("Type new wallet passphrase: ")?;
+      let passphrase_confirmation = read_password("Type passphrase again: ")?;
+      if passphrase != passphrase_confirmation {
+        return Err("Two passphrases are inconsistent".into())
+      }
+      store.encrypt(&passphrase)?;
+      println!("Wallet is encrypted");
+    }
+    ("changepassphrase", Some(_)) => {
+      if !store.is_encrypted() {
+        return Err("Wallet is not encrypted, use `encryptwallet` to encrypt it".into())
+      }
+      let old = read_password("Type old wallet passphrase: ")?;
+      let new = read_password("Type new wallet passphrase: ")?;
+      let new_confirmation = read_password("Type new passphrase again: ")?;
+      if new != new_confirmation {
+        return Err("Two passphrases are inconsistent".into())
+      }
+      store.change_passphrase(&old, &new)?;
+      println!("Wallet passphrase is changed");
+    }
+    ("walletpassphrase", Some(matches)) => {
+      let timeout = matches.value_of("timeout").unwrap();
+      let timeout = u64::from_str(timeout).map_err(|_err| "Invalid `timeout`")?;
+      if !store.is_encrypted() {
+        return Err("Wallet is not encrypted, use `encryptwallet` to encrypt it".into())
+      }
+      let passphrase = read_password("Wallet passphrase: ")?;
+      store.unlock(&passphrase)?;
+      if let Some(agent) = agent::Client::connect(&data_path, store.name()) {
+        agent.lock()?;
+      }
+      agent::spawn(&data_path, store.name(), &store.master_key().unwrap(), timeout)?;
+      store.lock();
+      println!("Wallet is unlocked for {} seconds", timeout);
+    }
+    ("walletlock", Some(_)) => {
+      match agent::Client::connect(&data_path, store.name()) {
+        Some(agent) => {
+          agent.lock()?;
+          println!("Wallet is locked");
+        },
+        None => println!("Wallet is not unlocked"),
+      }
+    }
+    ("agent", Some(matches)) => {
+      let timeout = matches.value_of("timeout").unwrap();
+      let timeout = u64::from_str(timeout).map_err(|_err| "Invalid `timeout`")?;
+      agent::run(&data_path, store.name(), timeout)?;
+    }
+    ("listaddresses", Some(matches)) => {
       let addresses = store.read_all();
-      for address in addresses {
+      for address in addresses.iter().filter(|address| !address.archived || matches.is_present("all")) {
         address.print();
       }
     }
+    ("checkwallet", Some(_)) => {
+      let conflicts = store.conflicts();
+      for conflict in conflicts.iter() {
+        println!("{}", conflict);
+      }
+      if conflicts.is_empty() {
+        println!("No conflict is found");
+      } else {
+        println!("\n{} conflicts are found, use `setlabel <address> <label>` to fix them", conflicts.len());
+      }
+    }
+    ("setlabel", Some(matches)) => {
+      let label = matches.value_of("label_or_address").unwrap();
+      let new_label = matches.value_of("new_label").unwrap();
+      store.set_label(label, new_label)?;
+      println!("`{}` is renamed to `{}`", label, new_label);
+    }
+    ("removeaddress", Some(matches)) => {
+      let label = matches.value_of("label_or_address").unwrap();
+      let address = store.read(label).ok_or(format!("`{}` related address does not exist", label))?;
+      if !address.is_watchonly() && address.backed_up_at == 0 && !matches.is_present("force") {
+        let err = format!("`{}` has no verified backup, use `backup` or `splitbackup --output` first, or `--force` to remove it anyway", address.addr);
+        return Err(err.into())
+      }
+      store.remove(&address.addr)?;
+      println!("`{}` is removed", address.addr);
+    }
+    ("archiveaddress", Some(matches)) => {
+      let label = matches.value_of("label_or_address").unwrap();
+      if matches.is_present("undo") {
+        store.set_archived(label, false)?;
+        println!("`{}` is unarchived", label);
+      } else {
+        store.set_archived(label, true)?;
+        println!("`{}` is archived", label);
+      }
+    }
 
     ("restore", Some(matches)) => {
       let file = matches.value_of("file").unwrap();
 
+      if let Ok(batch) = BatchKeystore::parse_from_file(file.to_string()) {
+        let input = read_password("Password: ").ok();
+        let password = input.as_ref().map(|v| v.as_str());
+        let keystores = match batch.into_keystores(password) {
+          Ok(keystores) => keystores,
+          Err(_) => {
+            println!("Failed to decrypt batch file");
+            return Ok(())
+          }
+        };
+
+        for keystore in keystores {
+          let result = match Address::from_keystore(keystore.clone(), password, &networks) {
+            Ok(address) => Ok(address),
+            Err(_) => {
+              // the account may be encrypted by its own password
+              let prompt = format!("Password of `{}`: ", keystore.address);
+              let input = read_password(prompt.as_str()).ok();
+              Address::from_keystore(keystore.clone(), input.as_ref().map(|v| v.as_str()), &networks)
+            },
+          };
+          match result {
+            Ok(address) => {
+              match check_network(&address, &network).and_then(|_| save_address(&store, &network, address.clone())) {
+                Ok(_) => println!("{} is restored", address.addr),
+                Err(e) => println!("Failed to restore address {}: {}", address.addr, e),
+              }
+            },
+            Err(_) => println!("Failed to recover address {}", keystore.address),
+          }
+        }
+        return Ok(())
+      }
+
       let keystore = match Keystore::parse_from_file(file.to_string()) {
         Ok(keystore) => keystore,
         Err(_) => {
@@ -87,9 +641,17 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
         }
       };
 
-      let password = rpassword::read_password_from_tty(Some("Password: ")).ok();
-      if let Ok(address) = Address::from_keystore(keystore, password) {
-        store.save(address.clone());
+      let password = read_password("Password: ").ok();
+      if let Ok(mut address) = Address::from_keystore(keystore, password.as_ref().map(|v| v.as_str()), &networks) {
+        if let Some(label) = matches.value_of("label") {
+          address.label = label.to_string();
+        }
+        check_network(&address, &network)?;
+        if store.read(&address.addr).is_some() {
+          println!("`{}` already exists", address.addr);
+          return Ok(())
+        }
+        save_address(&store, &network, address.clone())?;
         println!("{} is restored", address.addr);
       } else {
         println!("Failed to recover address");
@@ -97,9 +659,45 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
       }
     }
     ("backup", Some(matches)) => {
+      if let Some(file) = matches.value_of("all") {
+        unlock_wallet(&store)?;
+        let addresses: Vec<Address> = store.read_all().into_iter().filter(|address| !address.is_watchonly()).collect();
+        if addresses.is_empty() {
+          println!("There is no address to backup");
+          return Ok(())
+        }
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+        let full_path = backup_path(file, format!("batch_exported_account_{}.json", now));
+        if full_path.exists() {
+          eprintln!("File `{}` aleady exists", full_path.to_str().unwrap());
+          return Ok(())
+        }
+
+        let input = read_password("Type password to encrypt seed: ").ok();
+        let confirmation = read_password("Type password again: ").ok();
+
+        if input != confirmation {
+          return Err("Two passwords are inconsistent".into())
+        }
+
+        let password = input.as_ref().map(|v| v.as_str());
+        let keystores = addresses.iter().map(|address| address.into_keystore(password, &networks)).collect();
+        let batch = BatchKeystore::from_keystores(keystores, password).map_err(|_| "Failed to encrypt batch file")?;
+
+        if let Err(e) =  fs::write(full_path.clone(), batch.to_json()) {
+          println!("Failed to write to file: {:?}", e);
+        } else {
+          println!("{} addresses are backed up to file `{}`", addresses.len(), full_path.to_str().unwrap());
+          verify_backup(&store, &networks, &full_path, &addresses, password)?;
+        }
+        return Ok(())
+      }
+
       let label  = matches.value_of("label").unwrap();
       let file  = matches.value_of("path").unwrap();
 
+      unlock_wallet(&store)?;
       let address = match store.read(label) {
         Some(address) => address,
         None => {
@@ -108,36 +706,144 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
         }
       };
 
-      let path = Path::new(file);
-      let full_path = if path.ends_with("/") || path.is_dir() { // dir
-        let file_name = format!("{}.json", address.addr.as_str());
-        let mut path = path.to_path_buf();
-        path.push(file_name);
-        path
-      } else { // file
-        path.to_path_buf()
-      };
+      let full_path = backup_path(file, format!("{}.json", address.addr.as_str()));
 
       if full_path.exists() {
         eprintln!("File `{}` aleady exists", full_path.to_str().unwrap());
         return Ok(())
       }
 
-      let password = rpassword::read_password_from_tty(Some("Type password to encrypt seed: ")).ok();
-      let password_confirmation = rpassword::read_password_from_tty(Some("Type password again: ")).ok();
+      let input = read_password("Type password to encrypt seed: ").ok();
+      let confirmation = read_password("Type password again: ").ok();
 
-      if password != password_confirmation {
+      if input != confirmation {
         return Err("Two passwords are inconsistent".into())
       }
 
-      let keystore = address.into_keystore(password);
+      let password = input.as_ref().map(|v| v.as_str());
+      let keystore = if matches.is_present("legacy") {
+        address.into_legacy_keystore(password, &networks)
+      } else {
+        address.into_keystore(password, &networks)
+      };
 
       if let Err(e) =  fs::write(full_path.clone(), keystore.to_json()) {
         println!("Failed to write to file: {:?}", e);
       } else {
         println!("Address `{}` is backed up to file `{}`", address.addr, full_path.to_str().unwrap());
+        verify_backup(&store, &networks, &full_path, &[address.clone()], password)?;
       }
     },
+    ("splitbackup", Some(matches)) => {
+      let label = matches.value_of("label").unwrap();
+      let threshold = matches.value_of("threshold").unwrap().parse::<u8>().map_err(|_| "Invalid threshold")?;
+      let total = matches.value_of("shares").unwrap().parse::<u8>().map_err(|_| "Invalid number of shares")?;
+      if threshold < 2 || threshold > total {
+        return Err("Threshold must be at least 2 and not greater than the number of shares".into())
+      }
+
+      unlock_wallet(&store)?;
+      let address = store.read(label).ok_or("The label or address does not exist")?;
+      if address.is_watchonly() {
+        let err = format!("Watchonly address `{}` can not be backed up", address.label);
+        return Err(err.into());
+      }
+      let shares = shamir::split(&address.seed[..], threshold, total).map_err(|_| "Failed to split seed")?;
+
+      if let Some(dir) = matches.value_of("output") {
+        let dir = Path::new(dir);
+        let paths: Vec<PathBuf> = shares.iter().map(|share| {
+          dir.join(format!("{}_share_{}_of_{}.json", address.addr, share.index, total))
+        }).collect();
+        if let Some(path) = paths.iter().find(|path| path.exists()) {
+          eprintln!("File `{}` aleady exists", path.to_str().unwrap());
+          return Ok(())
+        }
+
+        fs::create_dir_all(dir)?;
+        for (share, path) in shares.iter().zip(paths.iter()) {
+          let file = ShareFile {
+            address: address.addr.clone(),
+            crypto_type: address.crypto_type.clone(),
+            path: address.path.clone(),
+            index: share.index,
+            threshold: share.threshold,
+            share: share.to_words().map_err(|_| "Failed to encode share")?,
+          };
+          fs::write(path, file.to_json())?;
+          println!("Share {} is written to file `{}`", share.index, path.to_str().unwrap());
+        }
+
+        // all shares are read back, the backup is verified if they rebuild the same address
+        let restored: Vec<Share> = paths.iter().filter_map(|path| {
+          ShareFile::parse_from_file(path.to_str().unwrap().to_string()).ok()?.to_share().ok()
+        }).collect();
+        let verified = restored.len() == shares.len() && shamir::combine(&restored).ok().and_then(|seed| {
+          recover_address(&seed, Some(address.crypto_type.as_str()), &address.path, &address.addr)
+        }).is_some();
+        if !verified {
+          return Err("Failed to verify the share files, please check them and backup again".into())
+        }
+        store.set_backed_up(&address.addr)?;
+      } else {
+        println!("Address: {}", address.addr);
+        if !address.path.is_empty() {
+          println!("Derivation path: {}", address.path);
+        }
+        println!("Write down and keep the shares separately, any {} of them can recover the address:\n", threshold);
+        for share in shares.iter() {
+          let words = Zeroizing::new(share.to_words().map_err(|_| "Failed to encode share")?);
+          println!("  {}\n", words.as_str());
+        }
+      }
+    }
+    ("combinebackup", Some(matches)) => {
+      let label = matches.value_of("label").unwrap();
+      store.check_label(label)?;
+      let mut shares: Vec<Share> = vec![];
+      let (expected, crypto_type, path) = if let Some(files) = matches.values_of("files") {
+        let mut info: Option<(String, String, String)> = None;
+        for file in files {
+          let share_file = ShareFile::parse_from_file(file.to_string()).map_err(|_| format!("Invalid share file `{}`", file))?;
+          let current = (share_file.address.clone(), share_file.crypto_type.clone(), share_file.path.clone());
+          if info.is_some() && info.as_ref() != Some(&current) {
+            return Err("Shares do not belong to the same address".into())
+          }
+          shares.push(share_file.to_share().map_err(|_| format!("Invalid share in file `{}`", file))?);
+          info = Some(current);
+        }
+        let (addr, crypto_type, path) = info.unwrap();
+        (addr, Some(crypto_type), path)
+      } else {
+        let expected = matches.value_of("address").ok_or("The address is required to recover from word lists")?;
+        let path = DerivationPath::parse(matches.value_of("path").unwrap_or("")).map_err(|_| "Invalid derivation path")?;
+        if path.password.is_some() {
+          return Err("Password `///` can only be used with mnemonic phrase".into())
+        }
+        let first = Share::from_words(&read_password("Share #1: ")?).map_err(|_| "Invalid share")?;
+        let threshold = first.threshold;
+        shares.push(first);
+        for i in 2..=threshold {
+          let share = Share::from_words(&read_password(format!("Share #{}: ", i).as_str())?).map_err(|_| "Invalid share")?;
+          shares.push(share);
+        }
+        (expected.to_string(), None, path.path)
+      };
+
+      let seed = shamir::combine(&shares).map_err(|_| "Not enough shares or the shares are invalid")?;
+      AccountId::from_ss58check(&expected).map_err(|_err| "Invalid address" )?;
+      // the crypto type is unknown for word lists, so try each of them until the address matches
+      let recovered = recover_address(&seed, crypto_type.as_ref().map(|t| t.as_str()), &path, &expected);
+      let mut address = recovered.ok_or(format!("Recovered address does not match `{}`", expected))?;
+
+      if store.read(&address.addr).is_some() {
+        println!("`{}` already exists", address.addr);
+        return Ok(())
+      }
+      address.label = label.to_string();
+      save_address(&store, &network, address.clone())?;
+      println!("{}", address.addr);
+    }
     ("transfer", Some(matches)) => {
       let from  = matches.value_of("from").unwrap();
       let to  = matches.value_of("to").unwrap();
@@ -148,60 +854,81 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
         let err = format!("Watchonly address `{}` can not do transfer", from_address.label);
         return Err(err.into());
       }
+      check_network(&from_address, &network)?;
 
       let to_addr  = match store.read(to) {
-        Some(v) => v.addr,
+        Some(v) => v.addr.clone(),
         None => to.to_string(),
       };
 
 
       let from_account_id = AccountId::from_ss58check(&from_address.addr).map_err(error::Error::PublicKey)?;
-      let to_account_id = AccountId::from_ss58check(&to_addr).map_err(error::Error::PublicKey)?;
-
-      let config = rpc::Config::parse_from_file(config_file.as_path())?;
-      let url = config.get_url(Network::Polkadot).ok_or("rpc url is not set")?;
-      let rpc = Rpc::new(url.clone()).await;
-      let genesis_hash = rpc.genesis_hash().await?;
-      let info: AccountInfo = rpc.get_account_info(from_account_id.clone()).await?;
-      let properties = rpc.system_properties().await?;
-      let decimals = properties["tokenDecimals"].as_u64().unwrap();
-      let multipler: Decimal = 10u64.saturating_pow(decimals as u32).into();
-      let amount = value.mul(multipler).to_u128().unwrap();
-
-      let call = Call::Balances(BalancesCall::transfer(to_account_id, amount));
-      let xt = match from_address.crypto_type.as_str() {
-        "sr25519" => {
-          let signer = from_address.into_pair::<Sr25519>();
-          transfer::make_extrinsic::<Sr25519>(call, info.nonce, signer, genesis_hash)?
-        },
-        "ed25519" => { 
-          let signer =  from_address.into_pair::<Ed25519>();
-          transfer::make_extrinsic::<Ed25519>(call, info.nonce, signer, genesis_hash)?
-        },
-        "ecdsa" => { 
-          let signer = from_address.into_pair::<Ecdsa>();
-          transfer::make_extrinsic::<Ecdsa>(call, info.nonce, signer, genesis_hash)?
-        },
-        _ => unreachable!(),
+      let to_account_id = parse_account(&to_addr, &network)?;
+
+      // the nonce, runtime version and submission all go to the endpoint which is verified, it never fails over
+      let rpc = connect(&matches, &config, &network).await?.pin();
+      let PreparedTransfer { call, nonce, genesis_hash, version } = prepare_transfer(&rpc, &network, &from_account_id, to_account_id, value).await?;
+      let agent = if store.is_encrypted() && store.is_locked() {
+        agent::Client::connect(&data_path, store.name())
+      } else {
+        None
+      };
+
+      let xt = if let Some(agent) = agent {
+        // the seed is kept in unlock agent, only the payload is sent to agent
+        let raw_payload = transfer::make_payload(call, nonce, genesis_hash, &version);
+        let payload = raw_payload.using_encoded(|payload| payload.to_vec());
+        let (public, signature) = agent.sign(&from_address.addr, &payload[..])?;
+        match from_address.crypto_type.as_str() {
+          "sr25519" => {
+            let public = sr25519::Public::from_slice(&public[..]);
+            let signature = sr25519::Signature::from_slice(&signature[..]);
+            transfer::make_signed_extrinsic::<Sr25519>(raw_payload, public, signature)?
+          },
+          "ed25519" => {
+            let public = ed25519::Public::from_slice(&public[..]);
+            let signature = ed25519::Signature::from_slice(&signature[..]);
+            transfer::make_signed_extrinsic::<Ed25519>(raw_payload, public, signature)?
+          },
+          "ecdsa" => {
+            let public = ecdsa::Public::from_slice(&public[..]);
+            let signature = ecdsa::Signature::from_slice(&signature[..]);
+            transfer::make_signed_extrinsic::<Ecdsa>(raw_payload, public, signature)?
+          },
+          _ => unreachable!(),
+        }
+      } else {
+        unlock_wallet(&store)?;
+        let from_address = store.read(from).ok_or("`from` address does not exists")?;
+        match from_address.crypto_type.as_str() {
+          "sr25519" => {
+            let signer = from_address.into_pair::<Sr25519>();
+            transfer::make_extrinsic::<Sr25519>(call, nonce, signer, genesis_hash, &version)?
+          },
+          "ed25519" => { 
+            let signer =  from_address.into_pair::<Ed25519>();
+            transfer::make_extrinsic::<Ed25519>(call, nonce, signer, genesis_hash, &version)?
+          },
+          "ecdsa" => { 
+            let signer = from_address.into_pair::<Ecdsa>();
+            transfer::make_extrinsic::<Ecdsa>(call, nonce, signer, genesis_hash, &version)?
+          },
+          _ => unreachable!(),
+        }
       };
       let xt_hash = rpc.submit_extrinsic(xt).await?;
       println!("{:?}", xt_hash);
     },
-    ("getbalances", Some(_matches)) => {
-      let addresses = store.read_all();
+    ("getbalances", Some(matches)) => {
+      let addresses: Vec<Address> = store.read_all().into_iter()
+        .filter(|address| !address.archived && check_network(address, &network).is_ok())
+        .collect();
       let accounts = addresses.iter().map(|address| {
         AccountId::from_ss58check(&address.addr).unwrap()
       }).collect();
-      let config = rpc::Config::parse_from_file(config_file.as_path())?;
-      let url = config.get_url(Network::Polkadot).ok_or("rpc url is not set")?;
-      let rpc = Rpc::new(url).await;
-      let properties = rpc.system_properties().await?;
-      let balances = rpc.get_balances(accounts).await?;
-      let decimals = properties["tokenDecimals"].as_u64().unwrap();
-      let divider: Decimal = 10u64.saturating_pow(decimals as u32).into();
-      let unit = properties["tokenSymbol"].as_str().unwrap();
-      for (addr, balance) in balances {
-        let value = Decimal::from_str(balance.to_string().as_str()).unwrap().div(divider);
+      let rpc = connect(&matches, &config, &network).await?;
+      let (balances, unit) = get_balances(&rpc, &network, accounts).await?;
+      for (addr, value) in balances {
         println!("{:<55} {:>30} {}", addr, value, unit);
       }
     },
@@ -209,30 +936,28 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
       let addresses: Vec<Address> = match matches.value_of("label_or_address") {
         Some(label) => {
           match store.read(label) {
-            Some(address) => vec![address],
+            Some(address) => {
+              check_network(&address, &network)?;
+              vec![address]
+            },
             None => {
               let err = format!("`{}` related address does not exist.", label);
               return Err(err.into());
             },
           }
         },
-        None => store.read_all(),
+        None => store.read_all().into_iter().filter(|address| check_network(address, &network).is_ok()).collect(),
       };
 
-      let config = rpc::Config::parse_from_file(config_file.as_path())?;
-      let url = config.get_url(Network::Polkadot).ok_or("rpc url is not set")?;
       let accounts: Vec<AccountId> = addresses.iter().map(|address| AccountId::from_ss58check(address.addr.as_str()).unwrap()).collect();
-      // for account in accounts.iter() {
-      //  let addr = account.to_ss58check();
-      //  let store = FileStore::get(addr.as_str());
-      //  store.update(0);
-      // }
-      // sync::run(url, accounts).await?
+      // requests fail over to another endpoint of the network, so the scan goes on if an endpoint goes down
+      let rpc = connect(&matches, &config, &network).await?;
+      sync::run(data_path.clone(), rpc, accounts).await?;
     },
     ("listextrinsics", Some(matches)) => {
       let label = matches.value_of("label_or_address").unwrap();
       let address = store.read(label).ok_or("The label or address does not exists")?;
-      let xt_store = FileStore::get(&address.addr);
+      let xt_store = FileStore::open(&data_path, &address.addr);
       let mut xts = xt_store.read_all();
       xts.sort_by(|a,b| b.block_number.partial_cmp(&a.block_number).unwrap());
       for xt in xts.iter() {
@@ -240,27 +965,40 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
       }
     },
     ("setrpcurl", Some(matches)) => {
-      let url  = matches.value_of("url").unwrap();
-      let mut config = match rpc::Config::parse_from_file(config_file.as_path()) {
-        Ok(config) => config,
-        Err(_) => rpc::Config::new(),
-      };
-      let rpc = Rpc::new(url.to_string()).await;
-      let properties = rpc.system_properties().await?;
-      let network: Network = properties["ss58Format"].as_u64().unwrap().into();
-      config.set_url(network, url.to_string());
+      let urls: Vec<String> = matches.values_of("url").unwrap().map(String::from).collect();
+      let mut config = config;
+      let mut found: Option<&Network> = global_value(&matches, "network").and(Some(&network));
+      // every endpoint must serve the same network
+      for url in urls.iter() {
+        let rpc = Rpc::with_endpoints(vec![url.clone()], config.retry_policy()).await?;
+        let identity = rpc.chain_identity().await?;
+        let network = match found {
+          Some(network) => network,
+          None => networks.from_genesis_hash(&identity.genesis_hash)
+            .ok_or(format!("Unknown network of genesis hash `{}`, add it to `[[network]]` of config.toml or use `--network`", identity.genesis_hash))?,
+        };
+        network.verify(&identity).map_err(|err| format!("`{}`: {}", url, err))?;
+        found = Some(network);
+      }
+      let network = found.unwrap();
+      config.set_urls(&network.name, urls);
       config.write_to_file(config_file.as_path())?;
       config.print();
     },
     ("watchaddress", Some(matches)) => {
       let addr  = matches.value_of("addr").unwrap();
       let label  = matches.value_of("label").unwrap_or("");
-      let _check = AccountId::from_ss58check(addr).map_err(|_err| "Invalid address" )?;
+      parse_account(addr, &network)?;
 
       let mut address = Address::default();
       address.label = label.to_string();
       address.addr = addr.to_string();
-      store.save(address.clone());
+      address.network = network.name.clone();
+      if store.read(addr).is_some() {
+        println!("`{}` already exists", addr);
+        return Ok(())
+      }
+      store.save(address.clone())?;
       println!("`{}` is added", address.addr);
     },
     _ => {
@@ -269,4 +1007,110 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     }
   }
   Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::devnode::{ self, Chain, DECIMALS, ENDOWMENT };
+  use sp_core::{ ed25519, Pair };
+
+  fn temp_file(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("subwallet-main-{}-{}.json", name, std::process::id()))
+  }
+
+  /// A network of the devnode, its decimals and symbol differ from the ones the node reports
+  fn dev_network(chain: &Chain) -> Network {
+    Network {
+      name: "dev".to_string(),
+      genesis_hash: format!("{:#x}", chain.genesis_hash()),
+      chain: "Development".to_string(),
+      spec_name: String::new(),
+      symbol: "DEV".to_string(),
+      decimals: 10,
+      ..Network::default()
+    }
+  }
+
+  #[test]
+  fn test_verify_backup() {
+    let dir = env::temp_dir().join(format!("subwallet-main-verify-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let store = WalletStore::init(dir.to_str());
+    let networks = Networks::default();
+    let address = Address::generate::<Sr25519>();
+    store.save(address.clone()).unwrap();
+    let file = temp_file("backup");
+
+    let keystore = address.into_keystore(Some("111111"), &networks);
+    let batch = BatchKeystore::from_keystores(vec![keystore.clone()], Some("111111")).unwrap();
+    fs::write(&file, batch.to_json()).unwrap();
+    let err = verify_backup(&store, &networks, &file, &[address.clone()], Some("incorrect")).unwrap_err();
+    assert_eq!(err.to_string(), "Failed to decrypt the backup file");
+
+    fs::write(&file, "{}").unwrap();
+    let err = verify_backup(&store, &networks, &file, &[address.clone()], Some("111111")).unwrap_err();
+    assert_eq!(err.to_string(), "Failed to parse the backup file");
+
+    fs::write(&file, keystore.to_json()).unwrap();
+    let err = verify_backup(&store, &networks, &file, &[address.clone()], Some("incorrect")).unwrap_err();
+    assert!(err.to_string().starts_with("Failed to restore"));
+    assert_eq!(store.read(&address.addr).unwrap().backed_up_at, 0);
+
+    verify_backup(&store, &networks, &file, &[address.clone()], Some("111111")).unwrap();
+    assert!(store.read(&address.addr).unwrap().backed_up_at > 0);
+    fs::remove_file(&file).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_get_balances() {
+    let alice = AccountId::from(ed25519::Pair::from_string("//Alice", None).unwrap().public());
+    let bob = AccountId::from(ed25519::Pair::from_string("//Bob", None).unwrap().public());
+    let chain = Chain::new(&[alice.clone()]);
+    let network = dev_network(&chain);
+    let (url, _) = devnode::spawn(chain).await;
+    let file = temp_file("balances");
+
+    let rpc = Rpc::for_network(&network, vec![url], RetryPolicy::default()).await.unwrap().record(&file);
+    let recorded = get_balances(&rpc, &network, vec![alice.clone(), bob.clone()]).await.unwrap();
+    assert_eq!(recorded.0, vec![(alice.clone(), Decimal::from(ENDOWMENT as u64)), (bob.clone(), Decimal::from(0))]);
+    assert_eq!(recorded.1, "UNIT");
+
+    let replayed = get_balances(&Rpc::replay(&file).unwrap(), &network, vec![alice, bob]).await.unwrap();
+    assert_eq!(replayed, recorded);
+    fs::remove_file(&file).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_prepare_transfer() {
+    let alice = ed25519::Pair::from_string("//Alice", None).unwrap();
+    let from = AccountId::from(alice.public());
+    let to = AccountId::from(ed25519::Pair::from_string("//Bob", None).unwrap().public());
+    let chain = Chain::new(&[from.clone()]);
+    let network = dev_network(&chain);
+    let (url, _) = devnode::spawn(chain).await;
+    let file = temp_file("transfer");
+    let value = Decimal::from_str("1.5").unwrap();
+
+    let rpc = Rpc::for_network(&network, vec![url], RetryPolicy::default()).await.unwrap().record(&file).pin();
+    let recorded = prepare_transfer(&rpc, &network, &from, to.clone(), value).await.unwrap();
+    // the decimals of the node are used
+    assert_eq!(recorded.call, Call::Balances(BalancesCall::transfer(to.clone(), 15 * 10u128.pow(DECIMALS - 1))));
+    assert_eq!(recorded.nonce, 0);
+
+    let replayed = prepare_transfer(&Rpc::replay(&file).unwrap(), &network, &from, to.clone(), value).await.unwrap();
+    let sign = |prepared: PreparedTransfer| {
+      transfer::make_extrinsic::<Ed25519>(prepared.call, prepared.nonce, alice.clone(), prepared.genesis_hash, &prepared.version).unwrap()
+    };
+    // ed25519 signatures are deterministic, so the extrinsics are the same
+    let xt = sign(recorded);
+    assert_eq!(sign(replayed).encode(), xt.encode());
+    assert!(rpc.submit_extrinsic(xt).await.is_ok());
+    fs::remove_file(&file).unwrap();
+
+    // it refuses a node of another chain
+    let other = Network { genesis_hash: format!("{:#x}", Hash::repeat_byte(1)), ..network };
+    assert!(prepare_transfer(&rpc, &other, &from, to, value).await.is_err());
+  }
+}
@@ -0,0 +1,270 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use crate::error::Result;
+use crate::wallet::{ Address, Wallet };
+use crate::store::Extrinsics;
+use crate::backend;
+use crate::networks::LEGACY_UNKNOWN_NETWORK;
+
+const MAGIC: [u8; 4] = *b"SUBW";
+
+/// Schema header, it is the first field of every data file, so the version can be read before the layout is known
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Header {
+  magic: [u8; 4],
+  pub version: u32,
+}
+
+impl Header {
+  pub fn new(version: u32) -> Self {
+    Self {
+      magic: MAGIC,
+      version: version,
+    }
+  }
+}
+
+/// Data file whose layout is versioned
+pub trait Schema: Serialize + DeserializeOwned {
+  /// Version of the current layout
+  const VERSION: u32;
+
+  /// Decode data in the layout of `version` and upgrade it to the current layout
+  fn migrate(version: u32, data: &[u8]) -> Result<Self>;
+}
+
+/// Files written before the header is added are version 0
+pub fn version_of(data: &[u8]) -> u32 {
+  if data.len() >= 8 && data[..4] == MAGIC {
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&data[4..8]);
+    u32::from_le_bytes(version)
+  } else {
+    0
+  }
+}
+
+/// Upgrade the file in place, the old file is copied to `<file>.v<version>.bak` first.
+/// Returns the backup path if the file is upgraded.
+pub fn migrate_file<T: Schema>(path: &Path) -> Result<Option<PathBuf>> {
+  if !path.exists() {
+    return Ok(None)
+  }
+  let data = fs::read(path)?;
+  let version = version_of(&data);
+  if data.is_empty() || version == T::VERSION {
+    return Ok(None)
+  }
+  if version > T::VERSION {
+    let err = format!("`{}` is written by a newer version of subwallet", path.display());
+    return Err(err.into())
+  }
+
+  let value = T::migrate(version, &data)?;
+  let backup = append_extension(path, &format!("v{}.bak", version));
+  fs::copy(path, &backup)?;
+
+  let encoded = bincode::serialize(&value).map_err(|e| e.to_string())?;
+  backend::write_atomic(path, &encoded)?;
+  Ok(Some(backup))
+}
+
+/// Delete the copies `<file>.v<version>.bak` which `migrate_file` made of `path`
+pub fn remove_backups(path: &Path) -> Result<()> {
+  let (dir, prefix) = match (path.parent(), path.file_name()) {
+    (Some(dir), Some(name)) => (dir, format!("{}.v", name.to_string_lossy())),
+    _ => return Ok(()),
+  };
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(_) => return Ok(()),
+  };
+  for entry in entries {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().into_owned();
+    let is_backup = name.strip_prefix(&prefix)
+      .and_then(|rest| rest.strip_suffix(".bak"))
+      .map_or(false, |version| version.parse::<u32>().is_ok());
+    if is_backup {
+      fs::remove_file(entry.path())?;
+    }
+  }
+  Ok(())
+}
+
+pub fn append_extension(path: &Path, extension: &str) -> PathBuf {
+  let mut name = path.file_name().unwrap_or_default().to_os_string();
+  name.push(".");
+  name.push(extension);
+  path.with_file_name(name)
+}
+
+fn unsupported<T>(version: u32) -> Result<T> {
+  Err(format!("Unsupported schema version {}", version).into())
+}
+
+/// Layouts before the schema header is added
+mod v0 {
+  use serde::{Serialize, Deserialize};
+  use crate::store::Extrinsic;
+
+  #[derive(Serialize, Deserialize)]
+  pub struct Address {
+    pub addr: String,
+    pub label: String,
+    pub crypto_type: String,
+    pub seed: Vec<u8>,
+    pub network: String,
+    pub created_at: u64,
+  }
+
+  #[derive(Serialize, Deserialize)]
+  pub struct Wallet {
+    pub name: String,
+    pub address_book: Vec<Address>,
+  }
+
+  #[derive(Deserialize)]
+  pub struct Extrinsics {
+    pub addr: String,
+    pub xts: Vec<Extrinsic>,
+    pub scanned_at: u32,
+  }
+}
+
+impl Schema for Wallet {
+  /// 1: header, encrypted mnemonic phrase, derivation path, archived flag, backup time and master key
+  const VERSION: u32 = 1;
+
+  fn migrate(version: u32, data: &[u8]) -> Result<Self> {
+    match version {
+      0 => {
+        let old: v0::Wallet = bincode::deserialize(data).map_err(|e| e.to_string())?;
+        let mut wallet = Wallet::new(old.name);
+        for old_address in old.address_book {
+          let mut address = Address::default();
+          address.addr = old_address.addr;
+          address.label = old_address.label;
+          address.crypto_type = old_address.crypto_type;
+          address.seed = old_address.seed;
+          // the network enum recorded `unknow` for unrecognised genesis hashes, they belong to any network
+          address.network = match old_address.network.as_str() {
+            LEGACY_UNKNOWN_NETWORK => String::new(),
+            _ => old_address.network,
+          };
+          address.created_at = old_address.created_at;
+          wallet.address_book.push(address);
+        }
+        Ok(wallet)
+      },
+      _ => unsupported(version),
+    }
+  }
+}
+
+impl Schema for Extrinsics {
+  /// 1: header
+  const VERSION: u32 = 1;
+
+  fn migrate(version: u32, data: &[u8]) -> Result<Self> {
+    match version {
+      0 => {
+        let old: v0::Extrinsics = bincode::deserialize(data).map_err(|e| e.to_string())?;
+        let mut extrinsics = Extrinsics::new(old.addr);
+        for xt in old.xts {
+          extrinsics.add(xt);
+        }
+        extrinsics.scanned_at = old.scanned_at;
+        Ok(extrinsics)
+      },
+      _ => unsupported(version),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::wallet::WalletStore;
+  use crate::store::Extrinsic;
+  use crate::crypto::*;
+
+  fn setup(name: &str, fixture: &str) -> PathBuf {
+    set_default_ss58_version(Ss58AddressFormat::PolkadotAccount);
+    let dir = std::env::temp_dir().join(format!("subwallet-migration-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join(name);
+    fs::copy(fixture, &file).unwrap();
+    file
+  }
+
+  #[test]
+  fn test_version_of() {
+    assert_eq!(version_of(&fs::read("tests/fixtures/wallet_v0.bin").unwrap()), 0);
+    let header = bincode::serialize(&Header::new(3)).unwrap();
+    assert_eq!(version_of(&header), 3);
+    assert_eq!(version_of(&[]), 0);
+  }
+
+  #[test]
+  fn test_migrate_wallet_v0() {
+    let file = setup("polkadot", "tests/fixtures/wallet_v0.bin");
+    let backup = migrate_file::<Wallet>(&file).unwrap().unwrap();
+    assert_eq!(fs::read(&backup).unwrap(), fs::read("tests/fixtures/wallet_v0.bin").unwrap());
+    assert_eq!(version_of(&fs::read(&file).unwrap()), Wallet::VERSION);
+    // migrated file is not migrated again
+    assert!(migrate_file::<Wallet>(&file).unwrap().is_none());
+
+    let store = WalletStore::init(file.parent().unwrap().to_str());
+    let addresses = store.read_all();
+    assert_eq!(addresses.len(), 3);
+    assert!(!store.is_encrypted());
+
+    let ed = store.read("ed").unwrap();
+    assert_eq!(ed.addr, "14TouV8puYdaN72wMvNirvZsvcvYk5GRfTwJ7XF4P9fibL3m");
+    assert_eq!(ed.crypto_type, "ed25519");
+    assert_eq!(ed.created_at, 1591600763959u64);
+    assert_eq!(Ed25519::to_address(&ed.into_pair::<Ed25519>()), ed.addr);
+    assert!(!ed.has_phrase() && ed.path.is_empty() && !ed.archived);
+
+    let ec = store.read("13SmLJEpENqt1mdZsFjhq8BgYYTBPAgPxrjaad4yNd4Bgw7Y").unwrap();
+    assert_eq!(Ecdsa::to_address(&ec.into_pair::<Ecdsa>()), ec.addr);
+    assert!(store.read("watch").unwrap().is_watchonly());
+  }
+
+  #[test]
+  fn test_migrate_unknown_network() {
+    let file = setup("unknow", "tests/fixtures/wallet_v0.bin").with_file_name(crate::wallet::DEFAULT_WALLET_NAME);
+    fs::copy("tests/fixtures/wallet_v0.bin", &file).unwrap();
+    let mut old: v0::Wallet = bincode::deserialize(&fs::read(&file).unwrap()).unwrap();
+    for address in old.address_book.iter_mut() {
+      address.network = LEGACY_UNKNOWN_NETWORK.to_string();
+    }
+    fs::write(&file, bincode::serialize(&old).unwrap()).unwrap();
+    assert!(migrate_file::<Wallet>(&file).unwrap().is_some());
+
+    let store = WalletStore::init(file.parent().unwrap().to_str());
+    assert!(store.read_all().iter().all(|address| address.network.is_empty()));
+  }
+
+  #[test]
+  fn test_migrate_extrinsics_v0() {
+    let file = setup("xt", "tests/fixtures/xt_v0.bin");
+    assert!(migrate_file::<Extrinsics>(&file).unwrap().is_some());
+    let extrinsics: Extrinsics = bincode::deserialize(&fs::read(&file).unwrap()).unwrap();
+    assert_eq!(extrinsics.scanned_at, 100);
+    let xt: &Extrinsic = extrinsics.get(100, 1).unwrap();
+    assert_eq!(xt.module, "Balances");
+    assert_eq!(xt.signer, Some("14TouV8puYdaN72wMvNirvZsvcvYk5GRfTwJ7XF4P9fibL3m".to_string()));
+  }
+
+  #[test]
+  fn test_migrate_newer_version() {
+    let file = setup("newer", "tests/fixtures/wallet_v0.bin");
+    fs::write(&file, bincode::serialize(&Header::new(Wallet::VERSION + 1)).unwrap()).unwrap();
+    assert!(migrate_file::<Wallet>(&file).is_err());
+  }
+}
@@ -1,84 +1,183 @@
+use serde::{Serialize, Deserialize};
+
+pub const DEFAULT_NETWORK: &'static str = "polkadot";
+/// Network which subwallet before the network registry recorded for unrecognised genesis hashes
+pub const LEGACY_UNKNOWN_NETWORK: &'static str = "unknow";
 
 pub const POLKADOT_GENESIS_HASH: &'static str = "0x91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3";
 pub const KUSAMA_GENESIS_HASH: &'static str = "0xb0a8d493285c2df73290dfb7e61f870f17b41801197a149ca93654499ea3dafe";
+pub const WESTEND_GENESIS_HASH: &'static str = "0xe143f23803ac50e8f6f8e62695d1ce9e4e1d68aa36c1cd2cfd15340213f3423e";
 
-#[derive(Clone, Eq, PartialEq, Debug, Copy)]
-pub enum Network {
-  Polkadot,
-  Kusama,
-  Unknow,
+/// A chain in the network registry, it is defined by `[[network]]` in config.toml or built in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Network {
+  pub name: String,
+  pub ss58_format: u8,
+  /// Empty if it is not fixed, e.g. chains which are relaunched from time to time
+  #[serde(default)]
+  pub genesis_hash: String,
+  /// Chain name reported by `system_chain`, not checked if empty
+  #[serde(default)]
+  pub chain: String,
+  /// Runtime spec name, not checked if empty
+  #[serde(default)]
+  pub spec_name: String,
+  pub symbol: String,
+  pub decimals: u32,
+  /// Default RPC urls, the url set by `setrpcurl` takes precedence
+  #[serde(default)]
+  pub rpc_urls: Vec<String>,
 }
 
-impl Default for Network {
-  fn default() -> Self {
-    Network::Polkadot
+impl Network {
+  fn new(name: &str, ss58_format: u8, genesis_hash: &str, symbol: &str, decimals: u32, rpc_urls: &[&str]) -> Self {
+    Self {
+      name: name.to_string(),
+      ss58_format: ss58_format,
+      genesis_hash: genesis_hash.to_string(),
+      chain: String::new(),
+      spec_name: String::new(),
+      symbol: symbol.to_string(),
+      decimals: decimals,
+      rpc_urls: rpc_urls.iter().map(|url| url.to_string()).collect(),
+    }
+  }
+
+  fn with_identity(mut self, chain: &str, spec_name: &str) -> Self {
+    self.chain = chain.to_string();
+    self.spec_name = spec_name.to_string();
+    self
   }
-}
 
-impl From<&str> for Network {
-  fn from(name: &str) -> Network {
-    match name {
-      "polkadot" => Network::Polkadot,
-      "kusama" => Network::Kusama,
-      _ => Network::Unknow,
+  /// Check the chain a node reports is this network, all mismatches are listed in the error
+  pub fn verify(&self, identity: &ChainIdentity) -> std::result::Result<(), String> {
+    let mut mismatches = vec![];
+    if !self.genesis_hash.is_empty() && !self.genesis_hash.eq_ignore_ascii_case(&identity.genesis_hash) {
+      mismatches.push(format!("  genesis hash: expected {}, got {}", self.genesis_hash, identity.genesis_hash));
+    }
+    if !self.chain.is_empty() && !self.chain.eq_ignore_ascii_case(&identity.chain) {
+      mismatches.push(format!("  chain: expected {}, got {}", self.chain, identity.chain));
+    }
+    if !self.spec_name.is_empty() && self.spec_name != identity.spec_name {
+      mismatches.push(format!("  runtime: expected {}, got {}", self.spec_name, identity.spec_name));
+    }
+    if mismatches.is_empty() {
+      Ok(())
+    } else {
+      Err(format!("The node is not a {} node:\n{}\nCheck the rpc url or use `--network` to select the right network", self.name, mismatches.join("\n")))
     }
   }
-}
 
-impl From<u8> for Network {
-  fn from(v: u8) -> Network {
-    match v {
-      0 => Network::Polkadot,
-      2 => Network::Kusama,
-      _ => Network::Unknow,
+  /// Decoded genesis hash, `None` if it is not set
+  pub fn genesis_hash_bytes(&self) -> Option<Vec<u8>> {
+    let bytes = hex::decode(self.genesis_hash.trim_start_matches("0x")).ok()?;
+    if bytes.len() == 32 {
+      Some(bytes)
+    } else {
+      None
     }
   }
+
+  /// Networks known without config
+  pub fn builtin() -> Vec<Self> {
+    vec![
+      Self::new("polkadot", 0, POLKADOT_GENESIS_HASH, "DOT", 10, &["wss://rpc.polkadot.io"]).with_identity("Polkadot", "polkadot"),
+      Self::new("kusama", 2, KUSAMA_GENESIS_HASH, "KSM", 12, &["wss://kusama-rpc.polkadot.io"]).with_identity("Kusama", "kusama"),
+      Self::new("westend", 42, WESTEND_GENESIS_HASH, "WND", 12, &["wss://westend-rpc.polkadot.io"]).with_identity("Westend", "westend"),
+      Self::new("rococo", 42, "", "ROC", 12, &["wss://rococo-rpc.polkadot.io"]).with_identity("Rococo", "rococo"),
+      Self::new("uniarts", 45, "", "UART", 12, &[]),
+      Self::new("dev", 42, "", "UNIT", 12, &["ws://127.0.0.1:9944"]).with_identity("Development", ""),
+    ]
+  }
 }
 
-impl From<u64> for Network {
-  fn from(v: u64) -> Network {
-    match v {
-      0 => Network::Polkadot,
-      2 => Network::Kusama,
-      _ => Network::Unknow,
-    }
+impl Default for Network {
+  fn default() -> Self {
+    Networks::default().get(DEFAULT_NETWORK).cloned().unwrap()
   }
 }
 
-impl From<Network> for &'static str {
-  fn from(n: Network) -> &'static str {
-    match n {
-      Network::Polkadot => "polkadot",
-      Network::Kusama => "kusama",
-      _ => "unknow",
-    }
+/// The chain a node reports, it is checked against the network before signing
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainIdentity {
+  pub genesis_hash: String,
+  pub chain: String,
+  pub spec_name: String,
+}
+
+/// Built-in networks and the networks in config.toml
+#[derive(Clone, Debug, PartialEq)]
+pub struct Networks(Vec<Network>);
+
+impl Default for Networks {
+  fn default() -> Self {
+    Networks(Network::builtin())
   }
 }
 
-impl From<Network> for String {
-  fn from(n: Network) -> String {
-    match n {
-      Network::Polkadot => "polkadot".to_string(),
-      Network::Kusama => "kusama".to_string(),
-      _ => "unknow".to_string(),
+impl Networks {
+  /// A custom network replaces the built-in network of the same name
+  pub fn with(custom: &[Network]) -> Self {
+    let mut networks = Self::default();
+    for network in custom {
+      match networks.0.iter_mut().find(|n| n.name == network.name) {
+        Some(n) => *n = network.clone(),
+        None => networks.0.push(network.clone()),
+      }
     }
+    networks
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Network> {
+    self.0.iter().find(|n| n.name == name)
+  }
+
+  pub fn from_genesis_hash(&self, hash: &str) -> Option<&Network> {
+    self.0.iter().find(|n| !n.genesis_hash.is_empty() && n.genesis_hash.eq_ignore_ascii_case(hash))
   }
 }
 
-impl Network {
-  pub fn genesis_hash(&self) -> &'static str {
-    match self {
-      Network::Polkadot => POLKADOT_GENESIS_HASH,
-      Network::Kusama => KUSAMA_GENESIS_HASH,
-      _ => "",
-    }
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_builtin_networks() {
+    let networks = Networks::default();
+    assert_eq!(Network::default().name, DEFAULT_NETWORK);
+    assert_eq!(networks.from_genesis_hash(&KUSAMA_GENESIS_HASH.to_uppercase().replace("0X", "0x")).unwrap().name, "kusama");
+    assert!(networks.from_genesis_hash("").is_none());
+    assert_eq!(networks.get("kusama").unwrap().genesis_hash_bytes().unwrap()[..2], [0xb0, 0xa8]);
+    assert!(networks.get("dev").unwrap().genesis_hash_bytes().is_none());
   }
 
-  pub fn from_genesis_hash(hash: &str) -> Self {
-    match hash {
-      POLKADOT_GENESIS_HASH => Network::Polkadot,
-      KUSAMA_GENESIS_HASH => Network::Kusama,
-      _ => Network::Unknow,
-    }
+  #[test]
+  fn test_custom_networks() {
+    let dev = Network::new("dev", 42, "0x01", "DEV", 18, &["ws://10.0.0.2:9944"]);
+    let local = Network::new("local", 7, "0x02", "LOC", 12, &[]);
+    let networks = Networks::with(&[dev.clone(), local.clone()]);
+    assert_eq!(networks.get("dev"), Some(&dev));
+    assert_eq!(networks.from_genesis_hash("0x02"), Some(&local));
+    assert_eq!(networks.0.len(), Network::builtin().len() + 1);
+  }
+
+  #[test]
+  fn test_verify() {
+    let kusama = Networks::default().get("kusama").cloned().unwrap();
+    let mut identity = ChainIdentity {
+      genesis_hash: KUSAMA_GENESIS_HASH.to_string(),
+      chain: "Kusama".to_string(),
+      spec_name: "kusama".to_string(),
+    };
+    assert!(kusama.verify(&identity).is_ok());
+
+    identity.genesis_hash = POLKADOT_GENESIS_HASH.to_string();
+    identity.spec_name = "polkadot".to_string();
+    let err = kusama.verify(&identity).unwrap_err();
+    assert!(err.contains("genesis hash") && err.contains("runtime") && !err.contains("chain:"));
+
+    // only what is configured is checked
+    let local = Network::new("local", 42, "", "LOC", 12, &[]);
+    assert!(local.verify(&identity).is_ok());
   }
 }
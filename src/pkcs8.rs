@@ -4,6 +4,8 @@ use sodalite::{
   SECRETBOX_KEY_LEN, SECRETBOX_NONCE_LEN, 
   secretbox_open, secretbox
 };
+use scrypt::{ scrypt, ScryptParams as Params };
+use zeroize::Zeroizing;
 pub const SECRETBOX_BOXZEROBYTES: usize = 16;
 pub const SECRETBOX_ZEROBYTES: usize = 32;
 
@@ -13,30 +15,157 @@ pub const PKCS8_HEADER: [u8; 16] = [48, 83, 2, 1, 1, 48, 5, 6, 3, 43, 101, 112,
 pub const SEC_LENGTH: usize = 64;
 pub const SEED_LENGTH: usize = 32;
 
-pub fn decode(encoded: &[u8], passphrase: Option<String>) -> Result<(Vec<u8>, Vec<u8>), ()> {
-  let encoded_length = encoded.len();
+pub const SCRYPT_SALT_LENGTH: usize = 32;
+// salt + N + p + r, each parameter is an u32 in little endian
+pub const SCRYPT_LENGTH: usize = SCRYPT_SALT_LENGTH + 3 * 4;
 
+/// Scrypt parameters used by polkadot-js keystore version 3
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScryptParams {
+  pub n: u32,
+  pub p: u32,
+  pub r: u32,
+}
+
+impl Default for ScryptParams {
+  fn default() -> Self {
+    Self { n: 1 << 15, p: 1, r: 8 }
+  }
+}
+
+impl ScryptParams {
+  pub fn from_slice(slice: &[u8]) -> Self {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&slice[0..4]);
+    let n = u32::from_le_bytes(buf);
+    buf.copy_from_slice(&slice[4..8]);
+    let p = u32::from_le_bytes(buf);
+    buf.copy_from_slice(&slice[8..12]);
+    let r = u32::from_le_bytes(buf);
+    Self { n, p, r }
+  }
+
+  pub fn to_vec(&self) -> Vec<u8> {
+    let mut v = self.n.to_le_bytes().to_vec();
+    v.extend_from_slice(&self.p.to_le_bytes());
+    v.extend_from_slice(&self.r.to_le_bytes());
+    v
+  }
+}
+
+/// Derive secretbox key from passphrase with scrypt
+pub fn scrypt_key(passphrase: &[u8], salt: &[u8], params: ScryptParams) -> Result<Zeroizing<SecretboxKey>, ()> {
+  if !params.n.is_power_of_two() {
+    return Err(())
+  }
+  let log_n = params.n.trailing_zeros() as u8;
+  let params = Params::new(log_n, params.r, params.p).map_err(|_| () )?;
+  let mut key = Zeroizing::new([0u8; SECRETBOX_KEY_LEN]);
+  scrypt(passphrase, salt, &params, &mut key[..]).map_err(|_| () )?;
+  Ok(key)
+}
+
+/// Encrypt `msg` by secretbox, the result is prefixed by a random nonce
+pub fn seal(msg: &[u8], key: &SecretboxKey) -> Result<Vec<u8>, ()> {
+  let mut rng = thread_rng();
+  let mut nonce = [0u8; SECRETBOX_NONCE_LEN];
+  rng.fill(&mut nonce);
+
+  let mut padded = Zeroizing::new(vec![0u8; SECRETBOX_ZEROBYTES + msg.len()]);
+  padded[SECRETBOX_ZEROBYTES..].copy_from_slice(msg);
+
+  let mut encrypted = vec![0u8; padded.len()];
+  secretbox(&mut encrypted, &padded, &nonce, key).map_err(|_| () )?;
+
+  let result_length: usize = msg.len() + SECRETBOX_NONCE_LEN + SECRETBOX_BOXZEROBYTES;
+  let mut result = vec![0u8; result_length];
+
+  result[..SECRETBOX_NONCE_LEN].copy_from_slice(&nonce[..]);
+  result[SECRETBOX_NONCE_LEN..].copy_from_slice(&encrypted[SECRETBOX_BOXZEROBYTES..]);
+  Ok(result)
+}
+
+/// Decrypt `encoded` by secretbox, the decrypted data is wiped on drop
+pub fn open(encoded: &[u8], key: &SecretboxKey) -> Result<Zeroizing<Vec<u8>>, ()> {
+  if encoded.len() < SECRETBOX_NONCE_LEN + SECRETBOX_BOXZEROBYTES {
+    return Err(())
+  }
   let mut nonce: SecretboxNonce = [0u8; SECRETBOX_NONCE_LEN];
   nonce.copy_from_slice(&encoded[0..SECRETBOX_NONCE_LEN]);
 
-  let msg = match passphrase {
-    Some(passphrase) if !passphrase.is_empty() => {
-      let pass_bytes = passphrase.as_bytes();
-      let mut key: SecretboxKey = [0u8; SECRETBOX_KEY_LEN];
-      key[..pass_bytes.len()].copy_from_slice(pass_bytes);
+  let mut encrypted = vec![0u8; SECRETBOX_BOXZEROBYTES + encoded.len() - SECRETBOX_NONCE_LEN];
+  encrypted[SECRETBOX_BOXZEROBYTES..].copy_from_slice(&encoded[SECRETBOX_NONCE_LEN..]);
 
-      let mut encrypted = vec![0u8; SECRETBOX_BOXZEROBYTES + encoded_length - SECRETBOX_NONCE_LEN];
-      encrypted[SECRETBOX_BOXZEROBYTES..].copy_from_slice(&encoded[SECRETBOX_NONCE_LEN..]);
+  let mut raw = Zeroizing::new(vec![0u8; encrypted.len()]);
+  secretbox_open(&mut raw, &encrypted, &nonce, key).map_err(|_| () )?;
 
-      let mut raw = vec![0u8; encrypted.len()];
-      secretbox_open(&mut raw, &encrypted, &nonce, &key).map_err(|_| () )?;
+  let mut decrypted = Zeroizing::new(vec![0u8; raw.len() - SECRETBOX_ZEROBYTES]);
+  decrypted.copy_from_slice(&raw[SECRETBOX_ZEROBYTES..]);
+  Ok(decrypted)
+}
 
-      let mut decrypted = vec![0u8; raw.len() - SECRETBOX_ZEROBYTES];
-      decrypted.copy_from_slice(&raw[SECRETBOX_ZEROBYTES..]);
-      decrypted
+fn password_key(passphrase: &str) -> Zeroizing<SecretboxKey> {
+  let pass_bytes = passphrase.as_bytes();
+  let mut key = Zeroizing::new([0u8; SECRETBOX_KEY_LEN]);
+  key[..pass_bytes.len()].copy_from_slice(pass_bytes);
+  key
+}
+
+/// Returns public key and secret key, the secret key is wiped on drop
+pub fn decode(encoded: &[u8], passphrase: Option<&str>) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>), ()> {
+  let msg = match passphrase {
+    Some(passphrase) if !passphrase.is_empty() => {
+      let key = password_key(passphrase);
+      open(encoded, &key)?
     },
-    _ => encoded.to_vec(),
+    _ => Zeroizing::new(encoded.to_vec()),
   };
+  split(&msg)
+}
+
+/// Decode keystore version 3, the encoded bytes are prefixed by scrypt salt and parameters
+pub fn decode_scrypt(encoded: &[u8], passphrase: Option<&str>) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>), ()> {
+  let passphrase = match passphrase {
+    Some(v) if !v.is_empty() => v,
+    _ => return Err(()),
+  };
+  let msg = decrypt_scrypt(encoded, passphrase)?;
+  split(&msg)
+}
+
+/// Decrypt data of polkadot-js json format version 3.
+/// Only the parameters of polkadot-js are accepted, the file is untrusted and scrypt with a large N or r can use all memory.
+pub fn decrypt_scrypt(encoded: &[u8], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, ()> {
+  if encoded.len() < SCRYPT_LENGTH {
+    return Err(())
+  }
+  let salt = &encoded[..SCRYPT_SALT_LENGTH];
+  let params = ScryptParams::from_slice(&encoded[SCRYPT_SALT_LENGTH..SCRYPT_LENGTH]);
+  if params != ScryptParams::default() {
+    return Err(())
+  }
+  let key = scrypt_key(passphrase.as_bytes(), salt, params)?;
+  open(&encoded[SCRYPT_LENGTH..], &key)
+}
+
+/// Encrypt data into polkadot-js json format version 3
+pub fn encrypt_scrypt(msg: &[u8], passphrase: &str) -> Result<Vec<u8>, ()> {
+  let mut rng = thread_rng();
+  let mut salt = [0u8; SCRYPT_SALT_LENGTH];
+  rng.fill(&mut salt);
+  let params = ScryptParams::default();
+  let key = scrypt_key(passphrase.as_bytes(), &salt, params)?;
+
+  let mut result = salt.to_vec();
+  result.extend(params.to_vec());
+  result.extend(seal(msg, &key)?);
+  Ok(result)
+}
+
+fn split(msg: &[u8]) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>), ()> {
+  if msg.len() < PKCS8_HEADER.len() + SEED_LENGTH + PKCS8_DIVIDER.len() {
+    return Err(())
+  }
 
   let mut header = [0u8; PKCS8_HEADER.len()];
   header.copy_from_slice(&msg[..PKCS8_HEADER.len()]);
@@ -45,7 +174,11 @@ pub fn decode(encoded: &[u8], passphrase: Option<String>) -> Result<(Vec<u8>, Ve
     return Err(())
   }
 
-  let mut secret_key = [0u8; SEC_LENGTH];
+  if msg.len() < PKCS8_HEADER.len() + SEC_LENGTH + PKCS8_DIVIDER.len() {
+    return split_seed(msg)
+  }
+
+  let mut secret_key = Zeroizing::new([0u8; SEC_LENGTH]);
   let start: usize = PKCS8_HEADER.len();
   let end: usize = PKCS8_HEADER.len() + SEC_LENGTH;
   secret_key.copy_from_slice(&msg[start..end]);
@@ -56,40 +189,44 @@ pub fn decode(encoded: &[u8], passphrase: Option<String>) -> Result<(Vec<u8>, Ve
   divider.copy_from_slice(&msg[divider_offset..divider_end]);
 
   if divider != PKCS8_DIVIDER {
-    let mut secret_key = [0u8; SEED_LENGTH];
-    let start: usize = PKCS8_HEADER.len();
-    let end: usize = PKCS8_HEADER.len() + SEED_LENGTH;
-    secret_key.copy_from_slice(&msg[start..end]);
-
-    let divider_offset = PKCS8_HEADER.len() + secret_key.len();
-    let divider_end = divider_offset + PKCS8_DIVIDER.len();
-    let mut divider = [0u8; PKCS8_DIVIDER.len()];
-    divider.copy_from_slice(&msg[divider_offset..divider_end]);
-
-    if divider != PKCS8_DIVIDER {
-      return Err(())
-    }
-
-    let pub_offset = PKCS8_HEADER.len() + secret_key.len() + PKCS8_DIVIDER.len();
-    let mut public_key: Vec<u8> = vec![0u8; msg.len() - pub_offset];
-    public_key.copy_from_slice(&msg[pub_offset..]);
-
-    Ok((public_key.to_vec(), secret_key.to_vec()))
+    split_seed(msg)
   } else {
     let pub_offset = PKCS8_HEADER.len() + secret_key.len() + PKCS8_DIVIDER.len();
     let mut public_key = vec![0u8; msg.len() - pub_offset];
     public_key.copy_from_slice(&msg[pub_offset..]);
 
-    Ok((public_key.to_vec(), secret_key.to_vec()))
+    Ok((public_key.to_vec(), Zeroizing::new(secret_key.to_vec())))
+  }
+}
+
+fn split_seed(msg: &[u8]) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>), ()> {
+  let mut secret_key = Zeroizing::new([0u8; SEED_LENGTH]);
+  let start: usize = PKCS8_HEADER.len();
+  let end: usize = PKCS8_HEADER.len() + SEED_LENGTH;
+  secret_key.copy_from_slice(&msg[start..end]);
+
+  let divider_offset = PKCS8_HEADER.len() + secret_key.len();
+  let divider_end = divider_offset + PKCS8_DIVIDER.len();
+  let mut divider = [0u8; PKCS8_DIVIDER.len()];
+  divider.copy_from_slice(&msg[divider_offset..divider_end]);
+
+  if divider != PKCS8_DIVIDER {
+    return Err(())
   }
+
+  let pub_offset = PKCS8_HEADER.len() + secret_key.len() + PKCS8_DIVIDER.len();
+  let mut public_key: Vec<u8> = vec![0u8; msg.len() - pub_offset];
+  public_key.copy_from_slice(&msg[pub_offset..]);
+
+  Ok((public_key.to_vec(), Zeroizing::new(secret_key.to_vec())))
 }
 
-pub fn encode(secret_key: &[u8], public_key: &[u8], passphrase: Option<String>) -> Result<Vec<u8>, ()> {
+fn to_pkcs8(secret_key: &[u8], public_key: &[u8]) -> Zeroizing<Vec<u8>> {
   let sec_length: usize = secret_key.len();
   let pub_length: usize = public_key.len();
 
   let encoded_length: usize = PKCS8_HEADER.len() + sec_length + PKCS8_DIVIDER.len() + pub_length;
-  let mut encoded = vec![0u8; encoded_length];
+  let mut encoded = Zeroizing::new(vec![0u8; encoded_length]);
 
   let end = PKCS8_HEADER.len();
   encoded[..end].copy_from_slice(&PKCS8_HEADER[..]);
@@ -104,36 +241,94 @@ pub fn encode(secret_key: &[u8], public_key: &[u8], passphrase: Option<String>)
 
   let start = PKCS8_HEADER.len() + sec_length + PKCS8_DIVIDER.len();
   encoded[start..].copy_from_slice(&public_key[..]);
+  encoded
+}
 
+pub fn encode(secret_key: &[u8], public_key: &[u8], passphrase: Option<&str>) -> Result<Vec<u8>, ()> {
+  let encoded = to_pkcs8(secret_key, public_key);
 
-  let passphrase: String = match passphrase {
+  let passphrase = match passphrase {
     Some(v) if !v.is_empty() => v,
     _ => {
-      return Ok(encoded)
+      return Ok(encoded.to_vec())
     },
   };
 
-  let pass_bytes = passphrase.as_bytes();
-
-  let mut key = [0u8; SECRETBOX_KEY_LEN];
-  key[..pass_bytes.len()].copy_from_slice(pass_bytes);
+  let key = password_key(passphrase);
+  seal(&encoded, &key)
+}
 
-  let mut rng = thread_rng();
-  let mut nonce = [0u8; SECRETBOX_NONCE_LEN];
-  rng.fill(&mut nonce);
+/// Encode keystore version 3, secretbox key is derived from passphrase by scrypt
+pub fn encode_scrypt(secret_key: &[u8], public_key: &[u8], passphrase: Option<&str>) -> Result<Vec<u8>, ()> {
+  let encoded = to_pkcs8(secret_key, public_key);
 
-  let mut msg = vec![0u8; SECRETBOX_ZEROBYTES + encoded_length];
-  msg[SECRETBOX_ZEROBYTES..].copy_from_slice(&encoded[..]);
+  let passphrase = match passphrase {
+    Some(v) if !v.is_empty() => v,
+    _ => {
+      return Ok(encoded.to_vec())
+    },
+  };
 
-  let mut encrypted = vec![0u8; msg.len()];
-  secretbox(&mut encrypted, &msg, &nonce, &key).map_err(|_| () )?;
+  encrypt_scrypt(&encoded, passphrase)
+}
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use zeroize::Zeroize;
+
+  /// Wipe `buf` in place and check the live bytes are cleared before it is emptied
+  fn assert_wiped(mut buf: Zeroizing<Vec<u8>>) {
+    assert!(buf.iter().any(|b| *b != 0));
+    buf[..].zeroize();
+    assert!(buf.iter().all(|b| *b == 0));
+    buf.zeroize();
+    assert!(buf.is_empty());
+  }
 
-  let result_length: usize = encoded_length + SECRETBOX_NONCE_LEN + SECRETBOX_BOXZEROBYTES;
-  let mut result = vec![0u8; result_length];
+  #[test]
+  fn test_wipe_encode_buffers() {
+    let (secret_key, public_key) = ([7u8; SEED_LENGTH], [9u8; 32]);
+    // temporary buffers of `encode`
+    let encoded = to_pkcs8(&secret_key, &public_key);
+    assert_eq!(&encoded[PKCS8_HEADER.len()..PKCS8_HEADER.len() + SEED_LENGTH], &secret_key[..]);
+    assert_wiped(encoded);
+    let mut key = password_key("111111");
+    key.zeroize();
+    assert_eq!(*key, [0u8; SECRETBOX_KEY_LEN]);
+
+    let encrypted = encode(&secret_key, &public_key, Some("111111")).unwrap();
+    let (public, secret) = decode(&encrypted, Some("111111")).unwrap();
+    assert_eq!((&public[..], &secret[..]), (&public_key[..], &secret_key[..]));
+    assert_wiped(secret);
+  }
 
-  result[..SECRETBOX_NONCE_LEN].copy_from_slice(&nonce[..]);
-  result[SECRETBOX_NONCE_LEN..].copy_from_slice(&encrypted[SECRETBOX_BOXZEROBYTES..]);
+  #[test]
+  fn test_wipe_decrypted_buffers() {
+    let encrypted = encrypt_scrypt(b"secret", "111111").unwrap();
+    let decrypted = decrypt_scrypt(&encrypted, "111111").unwrap();
+    assert_eq!(&decrypted[..], b"secret");
+    assert_wiped(decrypted);
+
+    let salt = &encrypted[..SCRYPT_SALT_LENGTH];
+    let mut key = scrypt_key(b"111111", salt, ScryptParams::default()).unwrap();
+    let opened = open(&encrypted[SCRYPT_LENGTH..], &key).unwrap();
+    assert_eq!(&opened[..], b"secret");
+    assert_wiped(opened);
+    key.zeroize();
+    assert_eq!(*key, [0u8; SECRETBOX_KEY_LEN]);
+  }
 
-  Ok(result)
-}
\ No newline at end of file
+  #[test]
+  fn test_scrypt_params() {
+    let encrypted = encrypt_scrypt(b"secret", "password").unwrap();
+    assert_eq!(&decrypt_scrypt(&encrypted, "password").unwrap()[..], b"secret");
+
+    // N = 2^30, r = 8 would allocate about 1 TiB
+    let mut crafted = encrypted.clone();
+    let params = ScryptParams { n: 1 << 30, p: 1, r: 8 };
+    crafted[SCRYPT_SALT_LENGTH..SCRYPT_LENGTH].copy_from_slice(&params.to_vec());
+    assert_eq!(ScryptParams::from_slice(&crafted[SCRYPT_SALT_LENGTH..]), params);
+    assert!(decrypt_scrypt(&crafted, "password").is_err());
+  }
+}
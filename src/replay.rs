@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::error::{ Result, Error };
+use crate::backend;
+
+/// A JSON-RPC request and its response
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Exchange {
+  pub method: String,
+  pub params: Value,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub result: Option<Value>,
+  /// Message of the error if the request failed
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+/// Requests and responses captured from a node, they are saved to a JSON file as they are recorded.
+/// Responses to the same request are replayed in the order of recording, and the last one is repeated.
+pub struct Recording {
+  file: PathBuf,
+  exchanges: Mutex<Vec<Exchange>>,
+  /// Times each request has been replayed
+  replayed: Mutex<HashMap<String, usize>>,
+}
+
+impl Recording {
+  /// Start a new recording, `file` is overwritten
+  pub fn create(file: &Path) -> Self {
+    Self {
+      file: file.to_path_buf(),
+      exchanges: Mutex::new(vec![]),
+      replayed: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub fn open(file: &Path) -> Result<Self> {
+    let data = fs::read_to_string(file).map_err(|e| format!("Failed to read `{}`: {}", file.display(), e))?;
+    let exchanges: Vec<Exchange> = serde_json::from_str(&data)?;
+    Ok(Self {
+      file: file.to_path_buf(),
+      exchanges: Mutex::new(exchanges),
+      replayed: Mutex::new(HashMap::new()),
+    })
+  }
+
+  fn key(method: &str, params: &Value) -> String {
+    format!("{} {}", method, params)
+  }
+
+  pub fn record(&self, method: &str, params: Value, result: &Result<Value>) -> Result<()> {
+    let mut exchanges = self.exchanges.lock().unwrap();
+    exchanges.push(Exchange {
+      method: method.to_string(),
+      params: params,
+      result: result.as_ref().ok().cloned(),
+      error: result.as_ref().err().map(|err| err.to_string()),
+    });
+    let data = serde_json::to_vec_pretty(&*exchanges)?;
+    backend::write_atomic(&self.file, &data)?;
+    Ok(())
+  }
+
+  pub fn replay(&self, method: &str, params: &Value) -> Result<Value> {
+    let key = Self::key(method, params);
+    let exchanges = self.exchanges.lock().unwrap();
+    let found: Vec<&Exchange> = exchanges.iter()
+      .filter(|exchange| Self::key(&exchange.method, &exchange.params) == key)
+      .collect();
+    if found.is_empty() {
+      let err = format!("`{}` is not recorded in `{}`", key, self.file.display());
+      return Err(err.into())
+    }
+
+    let mut replayed = self.replayed.lock().unwrap();
+    let times = replayed.entry(key).or_insert(0);
+    let exchange = found[(*times).min(found.len() - 1)];
+    *times += 1;
+    match (&exchange.result, &exchange.error) {
+      (_, Some(err)) => Err(Error::Other(err.clone())),
+      (Some(result), None) => Ok(result.clone()),
+      (None, None) => Ok(Value::Null),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use crate::rpc::Rpc;
+  use crate::networks::Networks;
+
+  fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("subwallet-replay-{}-{}.json", name, std::process::id()))
+  }
+
+  #[test]
+  fn test_record_and_replay() {
+    let file = temp_file("record");
+    let recording = Recording::create(&file);
+    recording.record("chain_getHeader", json!([null]), &Ok(json!({ "number": "0x1" }))).unwrap();
+    recording.record("chain_getHeader", json!([null]), &Ok(json!({ "number": "0x2" }))).unwrap();
+    recording.record("system_chain", json!(null), &Err("Rpc error: closed".into())).unwrap();
+
+    let replay = Recording::open(&file).unwrap();
+    assert_eq!(replay.replay("chain_getHeader", &json!([null])).unwrap()["number"], "0x1");
+    assert_eq!(replay.replay("chain_getHeader", &json!([null])).unwrap()["number"], "0x2");
+    // the last response is repeated
+    assert_eq!(replay.replay("chain_getHeader", &json!([null])).unwrap()["number"], "0x2");
+    assert!(replay.replay("system_chain", &json!(null)).unwrap_err().to_string().contains("Rpc error: closed"));
+    assert!(replay.replay("chain_getHeader", &json!(["0x01"])).is_err());
+    fs::remove_file(file).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_replay_rpc() {
+    let rpc = Rpc::replay(Path::new("tests/fixtures/rpc_replay.json")).unwrap();
+    let identity = rpc.chain_identity().await.unwrap();
+    assert!(Networks::default().get("polkadot").unwrap().verify(&identity).is_ok());
+    let properties = rpc.system_properties().await.unwrap();
+    assert_eq!(properties.get("tokenSymbol").and_then(|v| v.as_str()), Some("DOT"));
+    assert!(rpc.header(None).await.is_err());
+  }
+}
@@ -34,12 +34,17 @@ use sp_rpc::{
 
 
 use runtime::{ SignedBlock, Header };
+use sp_runtime::traits::{ BlakeTwo256, Hash as HashT };
 use toml::{ Value as TomlValue, value::Table };
 
-use crate::networks::Network;
-use crate::error::{Result};
-use serde::{Serialize, Deserialize};
+use crate::networks::{ ChainIdentity, Network, Networks };
+use crate::error::{ Result, Error };
+use crate::replay::Recording;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
 use std::fs;
+use async_std::{ future::timeout, task };
 use std::path::Path;
 use crate::primitives::{
   Hash, 
@@ -50,10 +55,52 @@ use crate::primitives::{
   AccountId,
 };
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct WalletConfig {
+  /// The wallet used when `--wallet` is not given
+  pub default: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct NetworksConfig {
+  /// The network used when `--network` is not given
+  pub default: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct StorageConfig {
+  /// `file` (default) or `sqlite`
+  pub backend: Option<String>,
+}
+
+/// Timeout and retries of rpc requests, the defaults of `RetryPolicy` are used for fields not set
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ConnectionConfig {
+  /// Timeout of connecting and of each request, in seconds
+  pub timeout: Option<u64>,
+  /// Times to retry after every endpoint has failed
+  pub retries: Option<u32>,
+  /// Delay before the first retry in milliseconds, it doubles on each retry
+  pub backoff: Option<u64>,
+  /// Upper bound of the delay in milliseconds
+  pub max_backoff: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
   #[serde(rename = "rpc")]
   values: TomlValue,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  wallet: Option<WalletConfig>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  storage: Option<StorageConfig>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  networks: Option<NetworksConfig>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  connection: Option<ConnectionConfig>,
+  /// Networks added to the registry or overriding the built-in ones
+  #[serde(default, rename = "network", skip_serializing_if = "Vec::is_empty")]
+  custom_networks: Vec<Network>,
 }
 
 impl Config {
@@ -61,9 +108,37 @@ impl Config {
   pub fn new() -> Self {
     Self {
       values: TomlValue::Table(Table::new()),
+      wallet: None,
+      storage: None,
+      networks: None,
+      connection: None,
+      custom_networks: vec![],
+    }
+  }
+
+  pub fn default_wallet(&self) -> Option<String> {
+    self.wallet.as_ref()?.default.clone()
+  }
+
+  pub fn set_default_wallet(&mut self, name: String) {
+    self.wallet.get_or_insert_with(WalletConfig::default).default = Some(name);
+  }
+
+  pub fn retry_policy(&self) -> RetryPolicy {
+    let default = RetryPolicy::default();
+    let connection = self.connection.clone().unwrap_or_default();
+    RetryPolicy {
+      timeout: connection.timeout.map_or(default.timeout, Duration::from_secs),
+      retries: connection.retries.unwrap_or(default.retries),
+      backoff: connection.backoff.map_or(default.backoff, Duration::from_millis),
+      max_backoff: connection.max_backoff.map_or(default.max_backoff, Duration::from_millis),
     }
   }
 
+  pub fn storage_backend(&self) -> Option<String> {
+    self.storage.as_ref()?.backend.clone()
+  }
+
   pub fn parse_from_file(path: &Path) -> Result<Self> {
     if !path.exists() {
       return Err("rpc url is not set".into());
@@ -73,15 +148,43 @@ impl Config {
     Ok(config)
   }
 
-  pub fn get_url(&self, network: Network) -> Option<String> {
-    let table = self.values.as_table()?;
-    let url = table.get(network.into())?.as_str()?;
-    Some(url.into())
+  /// The network registry, built-in networks with the ones in config
+  pub fn networks(&self) -> Networks {
+    Networks::with(&self.custom_networks)
+  }
+
+  pub fn default_network(&self) -> Option<String> {
+    self.networks.as_ref()?.default.clone()
+  }
+
+  pub fn set_default_network(&mut self, name: String) {
+    self.networks.get_or_insert_with(NetworksConfig::default).default = Some(name);
+  }
+
+  /// Endpoints of network in order of priority, the urls set by `setrpcurl` go before the default urls of network
+  pub fn get_urls(&self, network: &Network) -> Vec<String> {
+    let mut urls: Vec<String> = match self.values.get(&network.name) {
+      Some(TomlValue::String(url)) => vec![url.clone()],
+      Some(TomlValue::Array(values)) => values.iter().filter_map(|url| url.as_str()).map(String::from).collect(),
+      _ => vec![],
+    };
+    for url in network.rpc_urls.iter() {
+      if !urls.contains(url) {
+        urls.push(url.clone());
+      }
+    }
+    urls
   }
 
-  pub fn set_url(&mut self, network: Network, url: String) {
+  /// A single url is saved as string, so config of older versions can read it
+  pub fn set_urls(&mut self, network: &str, urls: Vec<String>) {
     let table = self.values.as_table_mut().unwrap();
-    table.insert(network.into(), url.into());
+    let value = if urls.len() == 1 {
+      TomlValue::String(urls[0].clone())
+    } else {
+      TomlValue::Array(urls.into_iter().map(TomlValue::String).collect())
+    };
+    table.insert(network.into(), value);
   }
 
   pub fn write_to_file(&self, path: &Path) -> Result<()> {
@@ -100,7 +203,7 @@ impl Config {
     match table {
       Some(table) => {
         for (k, v) in table {
-          println!("{} = {}", k, v.as_str().unwrap());
+          println!("{} = {}", k, v);
         }
       },
       None => {
@@ -109,45 +212,349 @@ impl Config {
     }
   }
 }
+/// Result of `state_getRuntimeVersion`, fields not used are ignored
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeVersion {
+  pub spec_name: String,
+  pub spec_version: u32,
+  pub transaction_version: u32,
+}
+
+impl RuntimeVersion {
+  /// Version of the runtime subwallet is compiled with
+  pub fn compiled() -> Self {
+    Self {
+      spec_name: runtime::VERSION.spec_name.to_string(),
+      spec_version: runtime::VERSION.spec_version,
+      transaction_version: runtime::VERSION.transaction_version,
+    }
+  }
+}
+
+/// Result of `system_health`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+  pub peers: u64,
+  pub is_syncing: bool,
+  pub should_have_peers: bool,
+}
+
+impl Health {
+  /// The node is in sync and connected to the network
+  pub fn is_healthy(&self) -> bool {
+    !self.is_syncing && (self.peers > 0 || !self.should_have_peers)
+  }
+}
+
+/// Timeout of requests and exponential backoff between retries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+  pub timeout: Duration,
+  pub retries: u32,
+  pub backoff: Duration,
+  pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self {
+      timeout: Duration::from_secs(30),
+      retries: 3,
+      backoff: Duration::from_millis(500),
+      max_backoff: Duration::from_secs(10),
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// Delay before retry `attempt` (from 0), `jitter` in [0, 1) takes off up to half of it
+  pub fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+    let delay = self.backoff.checked_mul(2u32.saturating_pow(attempt)).unwrap_or(self.max_backoff).min(self.max_backoff);
+    delay.mul_f64(1.0 - jitter.max(0.0).min(1.0) / 2.0)
+  }
+
+  /// Wait before retry `attempt`
+  pub async fn wait(&self, attempt: u32) {
+    task::sleep(self.delay(attempt, rand::random())).await
+  }
+}
+
+/// Sending it again after the response is lost may be reported as an error, e.g. "already imported"
+const SUBMIT_EXTRINSIC: &'static str = "author_submitExtrinsic";
+
+#[derive(Clone)]
+enum Transport {
+  Remote {
+    /// Endpoints in order of priority
+    urls: Vec<String>,
+    /// An endpoint is only used if it serves this network
+    network: Option<Network>,
+    /// Index in `urls` and client of the endpoint in use, it is shared by clones
+    current: Arc<Mutex<(usize, Client)>>,
+  },
+  /// Responses are read from a recording, nothing is sent to a node
+  Replay(Arc<Recording>),
+}
+
+/// Client of a network's endpoints, requests fail over to the next healthy endpoint on error
 #[derive(Clone)]
 pub struct Rpc {
-  client: Client,
-  pub url: String
+  transport: Transport,
+  policy: RetryPolicy,
+  /// Requests and responses are recorded if it is set
+  recording: Option<Arc<Recording>>,
 }
 
 impl Rpc {
 
-  pub async fn new(url: String) -> Self {
-    let client = if url.starts_with("ws://") || url.starts_with("wss://") {
-      jsonrpsee::ws_client(&url).await.unwrap()
+  #[allow(dead_code)]
+  pub async fn new(url: String) -> Result<Self> {
+    Self::with_endpoints(vec![url], RetryPolicy::default()).await
+  }
+
+  /// Connect to the first healthy endpoint of `urls`, the chain it serves is not checked
+  pub async fn with_endpoints(urls: Vec<String>, policy: RetryPolicy) -> Result<Self> {
+    Self::open(urls, None, policy).await
+  }
+
+  /// Connect to the first healthy endpoint of `urls` which serves `network`, it is checked on every failover too
+  pub async fn for_network(network: &Network, urls: Vec<String>, policy: RetryPolicy) -> Result<Self> {
+    Self::open(urls, Some(network.clone()), policy).await
+  }
+
+  async fn open(urls: Vec<String>, network: Option<Network>, policy: RetryPolicy) -> Result<Self> {
+    if urls.is_empty() {
+      return Err("rpc url is not set".into())
+    }
+    let current = Self::select(&urls, 0, network.as_ref(), &policy).await?;
+    Ok(Self {
+      transport: Transport::Remote {
+        urls,
+        network,
+        current: Arc::new(Mutex::new(current)),
+      },
+      policy,
+      recording: None,
+    })
+  }
+
+  /// Keep using the current endpoint, e.g. while a transaction is made and signed. It reconnects to the endpoint
+  /// on error but never fails over, and it is not affected by failovers of `self`.
+  pub fn pin(&self) -> Self {
+    let transport = match &self.transport {
+      Transport::Remote { urls, network, current } => {
+        let (index, client) = current.lock().unwrap().clone();
+        Transport::Remote {
+          urls: vec![urls[index].clone()],
+          network: network.clone(),
+          current: Arc::new(Mutex::new((0, client))),
+        }
+      },
+      Transport::Replay(recording) => Transport::Replay(recording.clone()),
+    };
+    Self {
+      transport,
+      policy: self.policy,
+      recording: self.recording.clone(),
+    }
+  }
+
+  /// Timeout and retries of requests
+  pub fn policy(&self) -> RetryPolicy {
+    self.policy
+  }
+
+  /// Replay the responses recorded in `file`
+  pub fn replay(file: &Path) -> Result<Self> {
+    Ok(Self {
+      transport: Transport::Replay(Arc::new(Recording::open(file)?)),
+      policy: RetryPolicy::default(),
+      recording: None,
+    })
+  }
+
+  /// Record requests and responses to `file`
+  pub fn record(mut self, file: &Path) -> Self {
+    self.recording = Some(Arc::new(Recording::create(file)));
+    self
+  }
+
+  async fn connect(url: &str, policy: &RetryPolicy) -> Result<Client> {
+    if url.starts_with("ws://") || url.starts_with("wss://") {
+      let client = timeout(policy.timeout, jsonrpsee::ws_client(url)).await
+        .map_err(|_| Error::Timeout(format!("connecting to {}", url)))??;
+      Ok(client)
     } else if url.starts_with("http://") || url.starts_with("https://") {
-      jsonrpsee::http_client(&url)
+      Ok(jsonrpsee::http_client(url))
     } else {
-      panic!("Invalid url");
+      Err(Error::InvalidUrl(url.to_string()))
+    }
+  }
+
+  /// Send a request to `client` only, it is used to probe an endpoint
+  async fn call<T: DeserializeOwned>(client: &Client, method: &str, params: Params, policy: &RetryPolicy) -> Result<T> {
+    let result = timeout(policy.timeout, client.request(method, params)).await
+      .map_err(|_| Error::Timeout(method.to_string()))??;
+    Ok(result)
+  }
+
+  /// Identity of the chain served by `client`, all of it comes from the same endpoint
+  async fn identity(client: &Client, policy: &RetryPolicy) -> Result<ChainIdentity> {
+    let params = Params::Array(vec![to_json_value(0)?]);
+    let genesis_hash: Option<Hash> = Self::call(client, "chain_getBlockHash", params, policy).await?;
+    let genesis_hash = genesis_hash.ok_or("The node has no genesis block")?;
+    let params = Params::Array(vec![serde_json::Value::Null]);
+    let version: RuntimeVersion = Self::call(client, "state_getRuntimeVersion", params, policy).await?;
+    Ok(ChainIdentity {
+      genesis_hash: format!("{:#x}", genesis_hash),
+      chain: Self::call(client, "system_chain", Params::None, policy).await?,
+      spec_name: version.spec_name,
+    })
+  }
+
+  /// Probe endpoints from `from` in turn, the first one which is healthy and serves `network` is used
+  async fn select(urls: &[String], from: usize, network: Option<&Network>, policy: &RetryPolicy) -> Result<(usize, Client)> {
+    let mut errors = vec![];
+    for index in (0..urls.len()).map(|i| (from + i) % urls.len()) {
+      let url = &urls[index];
+      let client = match Self::connect(url, policy).await {
+        Ok(client) => client,
+        // the url will not become valid by retrying
+        Err(Error::InvalidUrl(url)) => return Err(Error::InvalidUrl(url)),
+        Err(err) => {
+          errors.push(format!("  {}: {}", url, err));
+          continue
+        },
+      };
+      match Self::call::<Health>(&client, "system_health", Params::None, policy).await {
+        Ok(health) if health.is_healthy() => (),
+        Ok(health) => {
+          errors.push(format!("  {}: not in sync, {:?}", url, health));
+          continue
+        },
+        Err(err) => {
+          errors.push(format!("  {}: {}", url, err));
+          continue
+        },
+      }
+      let network = match network {
+        Some(network) => network,
+        None => return Ok((index, client)),
+      };
+      match Self::identity(&client, policy).await.map(|identity| network.verify(&identity)) {
+        Ok(Ok(())) => return Ok((index, client)),
+        Ok(Err(err)) => errors.push(format!("  {}: {}", url, err)),
+        Err(err) => errors.push(format!("  {}: {}", url, err)),
+      }
+    }
+    Err(Error::Unavailable(errors.join("\n")))
+  }
+
+  async fn request<T: DeserializeOwned>(&self, method: &str, params: Params) -> Result<T> {
+    let params_value = to_json_value(&params)?;
+    let result = match &self.transport {
+      Transport::Remote { urls, network, current } => self.request_remote(urls, network.as_ref(), current, method, params).await,
+      Transport::Replay(recording) => recording.replay(method, &params_value),
     };
-    Self {
-      client,
-      url: url.clone(),
+    if let Some(recording) = &self.recording {
+      recording.record(method, params_value, &result)?;
+    }
+    Ok(serde_json::from_value(result?)?)
+  }
+
+  /// Send a request to the endpoints. On a connection error or timeout it fails over to the next healthy endpoint,
+  /// and once every endpoint has failed it waits with exponential backoff before trying them again, up to `retries` times.
+  /// Error responses of the node are returned at once, they are the same on every endpoint.
+  async fn request_remote(
+    &self,
+    urls: &[String],
+    network: Option<&Network>,
+    current: &Mutex<(usize, Client)>,
+    method: &str,
+    params: Params,
+  ) -> Result<serde_json::Value> {
+    let mut failures = 0;
+    let mut retries = 0;
+    loop {
+      let (index, client) = current.lock().unwrap().clone();
+      let err = match timeout(self.policy.timeout, client.request(method, params.clone())).await {
+        Ok(Ok(value)) => return Ok(value),
+        Ok(Err(err)) => Error::from(err),
+        Err(_) => Error::Timeout(method.to_string()),
+      };
+      if !err.is_transport() {
+        return Err(err)
+      }
+      // the extrinsic may be in the pool already, it is left to the caller to check
+      if method == SUBMIT_EXTRINSIC && matches!(err, Error::Timeout(_)) {
+        return Err(err)
+      }
+      failures += 1;
+      if failures % urls.len() == 0 {
+        if retries >= self.policy.retries {
+          return Err(err)
+        }
+        self.policy.wait(retries).await;
+        retries += 1;
+      }
+      // a clone may have failed over already
+      if current.lock().unwrap().0 != index {
+        continue
+      }
+      // the connection may be broken, so reconnect even if there is only one endpoint
+      if let Ok(next) = Self::select(urls, index + 1, network, &self.policy).await {
+        if next.0 != index {
+          eprintln!("`{}` failed: {}, switched to `{}`", urls[index], err, urls[next.0]);
+        }
+        *current.lock().unwrap() = next;
+      }
     }
   }
 
   /// Request system properties
   pub async fn system_properties(&self) -> Result<Properties> {
-    match self.client.request("system_properties", Params::None).await {
-      Ok(v) => Ok(v),
-      Err(err) => Err(format!("{:?}", err).into())
-    }
+    self.request("system_properties", Params::None).await
   }
 
-  /// Submit extrinsic
+  /// Request the chain name
+  pub async fn system_chain(&self) -> Result<String> {
+    let chain = self.request("system_chain", Params::None).await?;
+    Ok(chain)
+  }
+
+  /// Request the runtime version, returns the version of latest block by default
+  pub async fn runtime_version(&self, hash: Option<Hash>) -> Result<RuntimeVersion> {
+    let params = Params::Array(vec![to_json_value(hash)?]);
+    let version = self.request("state_getRuntimeVersion", params).await?;
+    Ok(version)
+  }
+
+  /// Genesis hash, chain name and runtime spec name of the node, they are requested from the same endpoint
+  pub async fn chain_identity(&self) -> Result<ChainIdentity> {
+    let rpc = self.pin();
+    Ok(ChainIdentity {
+      genesis_hash: format!("{:#x}", rpc.genesis_hash().await?),
+      chain: rpc.system_chain().await?,
+      spec_name: rpc.runtime_version(None).await?.spec_name,
+    })
+  }
+
+  /// Submit extrinsic, it is not sent again after a timeout as the node may have accepted it
   pub async fn submit_extrinsic<E: Encode>(
     &self,
     extrinsic: E,
   ) -> Result<Hash> {
     let bytes: Bytes = extrinsic.encode().into();
+    let hash = BlakeTwo256::hash(&bytes[..]);
     let params = Params::Array(vec![to_json_value(bytes)?]);
-    let xt_hash = self.client.request("author_submitExtrinsic", params).await?;
-    Ok(xt_hash)
+    match self.request(SUBMIT_EXTRINSIC, params).await {
+      Err(Error::Timeout(_)) => Err(Error::Timeout(format!(
+        "{}, it is unknown whether extrinsic {:#x} is submitted, check the account before sending it again", SUBMIT_EXTRINSIC, hash,
+      ))),
+      result => result,
+    }
   }
 
   /// Request the block hash by block number
@@ -157,7 +564,7 @@ impl Rpc {
   ) -> Result<Option<Hash>> {
     let block_number = block_number.map(ListOrValue::Value);
     let params = Params::Array(vec![to_json_value(block_number)?]);
-    let list_or_value  = self.client.request("chain_getBlockHash", params).await?;
+    let list_or_value  = self.request("chain_getBlockHash", params).await?;
     match list_or_value {
         ListOrValue::Value(hash) => Ok(hash),
         ListOrValue::List(_) => Err("Expected a Value, got a List".into()),
@@ -166,14 +573,18 @@ impl Rpc {
 
   /// Request genesis hash
   pub async fn genesis_hash(&self) -> Result<Hash> {
-    self.block_hash(Some(0)).await.map(|hash| hash.unwrap())
+    self.block_hash(Some(0)).await?.ok_or_else(|| "The node has no genesis block".into())
+  }
+
+  /// Request the SCALE encoded metadata, it can be of a version which `metadata` fails to decode
+  pub async fn metadata_bytes(&self, hash: Option<Hash>) -> Result<Bytes> {
+    let params = Params::Array(vec![to_json_value(hash)?]);
+    self.request("state_getMetadata", params).await
   }
 
   /// Request the metadata
-  #[allow(dead_code)]
   pub async fn metadata(&self, hash: Option<Hash>) -> Result<RuntimeMetadata> {
-    let params = Params::Array(vec![to_json_value(hash)?]);
-    let bytes: Bytes = self.client.request("state_getMetadata", params).await?;
+    let bytes = self.metadata_bytes(hash).await?;
     let meta: RuntimeMetadataPrefixed = Decode::decode(&mut &bytes[..])?;
     Ok(meta.1)
   }
@@ -181,14 +592,14 @@ impl Rpc {
   /// Requeset a block, returns latest block by default
   pub async fn block(&self, hash: Option<Hash>) -> Result<Option<SignedBlock>> {
     let params = Params::Array(vec![to_json_value(hash)?]);
-    let block = self.client.request("chain_getBlock", params).await?;
+    let block = self.request("chain_getBlock", params).await?;
     Ok(block)
   }
 
   /// Request a block header
   pub async fn header(&self, hash: Option<Hash>) -> Result<Option<Header>> {
     let params = Params::Array(vec![to_json_value(hash)?]);
-    let block = self.client.request("chain_getHeader", params).await?;
+    let block = self.request("chain_getHeader", params).await?;
     Ok(block)
   }
 
@@ -204,10 +615,7 @@ impl Rpc {
       to_json_value(from)?,
       to_json_value(to)?,
     ]);
-    self.client
-        .request("state_queryStorage", params)
-        .await
-        .map_err(Into::into)
+    self.request("state_queryStorage", params).await
   }
 
   /// Query storage at specific block, default is latest block hash
@@ -220,10 +628,7 @@ impl Rpc {
       to_json_value(keys)?,
       to_json_value(at)?,
     ]);
-    self.client
-        .request("state_queryStorageAt", params)
-        .await
-        .map_err(Into::into)
+    self.request("state_queryStorageAt", params).await
   }
 
   /// Get storage at specific block
@@ -236,31 +641,18 @@ impl Rpc {
       to_json_value(key)?,
       to_json_value(at)?,
     ]);
-    self.client
-        .request("state_getStorage", params)
-        .await
-        .map_err(Into::into)
+    self.request("state_getStorage", params).await
   }
 
   /// Get balances of addresses
   pub async fn get_balances(&self, accounts: Vec<AccountId>) -> Result<Vec<(AccountId, Balance)>> 
   {
-    let keys = accounts.iter().map(|id| {
-      let mut key = twox_128(b"System").to_vec();
-      key.extend(twox_128(b"Account").to_vec());
-      key.extend(
-        id.using_encoded(|v| {
-          let mut r = blake2_128(v).to_vec();
-          r.extend_from_slice(v);
-          r
-        })
-      );
-      StorageKey(key)
-    }).collect();
+    let keys = accounts.iter().map(account_key).collect();
 
     let sets: Vec<StorageChangeSet<Hash>> = self.query_storage_at(keys, None).await?;
 
-    let balances: Vec<Balance> = sets[0].changes.iter().map(|(_, data)| {
+    let set = sets.first().ok_or("The node returned no storage of the accounts")?;
+    let balances: Vec<Balance> = set.changes.iter().map(|(_, data)| {
       let info = data.clone().map_or(Default::default(), |v| {
         let input = v.0;
         AccountInfo::decode(&mut &input[..]).unwrap_or(Default::default())
@@ -273,17 +665,7 @@ impl Rpc {
 
   #[allow(dead_code)]
   pub async fn get_account_info(&self, account: AccountId) -> Result<AccountInfo> {
-    let mut key = twox_128(b"System").to_vec();
-    key.extend(twox_128(b"Account").to_vec());
-    key.extend(
-      account.using_encoded(|v| {
-        let mut r = blake2_128(v).to_vec();
-        r.extend_from_slice(v);
-        r
-      })
-    );
-
-    let data: Option<StorageData> = self.get_storage(StorageKey(key), None).await?;
+    let data: Option<StorageData> = self.get_storage(account_key(&account), None).await?;
 
     let info = match data {
       Some(v) => {
@@ -297,6 +679,20 @@ impl Rpc {
   }
 }
 
+/// Storage key of `System::Account` of account
+pub fn account_key(account: &AccountId) -> StorageKey {
+  let mut key = twox_128(b"System").to_vec();
+  key.extend(twox_128(b"Account").to_vec());
+  key.extend(
+    account.using_encoded(|v| {
+      let mut r = blake2_128(v).to_vec();
+      r.extend_from_slice(v);
+      r
+    })
+  );
+  StorageKey(key)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -306,6 +702,8 @@ mod tests {
   use crate::wallet::Address;
   use crate::primitives::{ AccountId };
   use std::str::FromStr;
+  use crate::devnode::{ self, Chain };
+  use std::path::PathBuf;
 
   #[test]
   fn test_config_parse_from_file() {
@@ -313,17 +711,147 @@ mod tests {
     assert!(config.values.is_table());
   }
   #[test]
-  fn test_config_get_url() {
+  fn test_config_get_urls() {
     let config = Config::parse_from_file("tests/fixtures/rpc_config.toml".as_ref()).unwrap();
-    let url = config.get_url(Network::Polkadot).unwrap();
-    assert_eq!(url, "wss://rpc.polkadot.io".to_string())
+    let urls = config.get_urls(config.networks().get("kusama").unwrap());
+    assert_eq!(urls, vec!["wss://kusama-rpc.polkadot.io".to_string()])
   }
 
   #[test]
-  fn test_config_set_url() {
+  fn test_config_set_urls() {
     let mut config = Config::new();
-    config.set_url(Network::Polkadot, "xxxx".to_string());
-    assert_eq!(config.get_url(Network::Polkadot), Some("xxxx".to_string()));
+    let network = Network::default();
+    assert_eq!(config.get_urls(&network), vec!["wss://rpc.polkadot.io".to_string()]);
+    config.set_urls(&network.name, vec!["xxxx".to_string()]);
+    assert_eq!(config.get_urls(&network), vec!["xxxx".to_string(), "wss://rpc.polkadot.io".to_string()]);
+    assert!(config.to_string().unwrap().contains("polkadot = \"xxxx\""));
+
+    config.set_urls(&network.name, vec!["b".to_string(), "a".to_string()]);
+    let config: Config = toml::from_str(&config.to_string().unwrap()).unwrap();
+    assert_eq!(config.get_urls(&network), vec!["b".to_string(), "a".to_string(), "wss://rpc.polkadot.io".to_string()]);
+  }
+
+  #[test]
+  fn test_retry_policy() {
+    let data = r#"
+[rpc]
+
+[connection]
+timeout = 5
+retries = 6
+"#;
+    let config: Config = toml::from_str(data).unwrap();
+    let policy = config.retry_policy();
+    assert_eq!(policy.timeout, Duration::from_secs(5));
+    assert_eq!(policy.retries, 6);
+    assert_eq!(policy.backoff, RetryPolicy::default().backoff);
+
+    assert_eq!(policy.delay(0, 0.0), Duration::from_millis(500));
+    assert_eq!(policy.delay(2, 0.0), Duration::from_millis(2000));
+    assert_eq!(policy.delay(2, 0.5), Duration::from_millis(1500));
+    assert_eq!(policy.delay(10, 0.0), policy.max_backoff);
+    assert_eq!(policy.delay(100, 0.0), policy.max_backoff);
+  }
+
+  #[test]
+  fn test_transport_errors() {
+    assert!(Error::Timeout("chain_getHeader".into()).is_transport());
+    assert!(Error::Unavailable("  ws://127.0.0.1:9944: connection refused".into()).is_transport());
+    assert!(!Error::InvalidUrl("rpc.polkadot.io".into()).is_transport());
+    assert!(!Error::Other("1010: Invalid Transaction".into()).is_transport());
+  }
+
+  #[tokio::test]
+  async fn test_invalid_url() {
+    match Rpc::new("rpc.polkadot.io".into()).await {
+      Err(Error::InvalidUrl(url)) => assert_eq!(url, "rpc.polkadot.io"),
+      _ => unreachable!(),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_endpoints_are_verified() {
+    use sp_core::Pair;
+    let alice = sp_core::ed25519::Pair::from_string("//Alice", None).unwrap();
+    let (other, local) = (Chain::new(&[]), Chain::new(&[AccountId::from(alice.public())]));
+    let network = Network {
+      name: "local".to_string(),
+      genesis_hash: format!("{:#x}", local.genesis_hash()),
+      chain: "Development".to_string(),
+      spec_name: String::new(),
+      ..Network::default()
+    };
+    let (other_url, _) = devnode::spawn(other).await;
+    let (local_url, _) = devnode::spawn(local).await;
+    let policy = RetryPolicy { retries: 0, ..RetryPolicy::default() };
+
+    // the first endpoint serves another chain, so it is skipped
+    let rpc = Rpc::for_network(&network, vec![other_url.clone(), local_url], policy).await.unwrap();
+    assert!(network.verify(&rpc.chain_identity().await.unwrap()).is_ok());
+    assert!(network.verify(&rpc.pin().chain_identity().await.unwrap()).is_ok());
+    match Rpc::for_network(&network, vec![other_url.clone()], policy).await {
+      Err(Error::Unavailable(err)) => assert!(err.contains(&other_url) && err.contains("genesis hash")),
+      _ => unreachable!(),
+    }
+    assert!(Rpc::with_endpoints(vec![other_url], policy).await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_error_response_is_not_retried() {
+    let (url, _) = devnode::spawn(Chain::new(&[])).await;
+    let policy = RetryPolicy { retries: 3, backoff: Duration::from_secs(5), ..RetryPolicy::default() };
+    let rpc = Rpc::with_endpoints(vec![url], policy).await.unwrap();
+    let start = std::time::Instant::now();
+    let err = rpc.submit_extrinsic(vec![1u8, 2, 3]).await.unwrap_err();
+    assert!(!err.is_transport(), "{}", err);
+    assert!(err.to_string().contains("Invalid Transaction"));
+    // it would wait 5 seconds before a retry
+    assert!(start.elapsed() < Duration::from_secs(5));
+  }
+
+  #[test]
+  fn test_health() {
+    let health: Health = serde_json::from_str(r#"{"peers":12,"isSyncing":false,"shouldHavePeers":true}"#).unwrap();
+    assert!(health.is_healthy());
+    let dev: Health = serde_json::from_str(r#"{"peers":0,"isSyncing":false,"shouldHavePeers":false}"#).unwrap();
+    assert!(dev.is_healthy());
+    let syncing = Health { is_syncing: true, ..health.clone() };
+    assert!(!syncing.is_healthy());
+    let alone = Health { peers: 0, ..health };
+    assert!(!alone.is_healthy());
+  }
+
+  #[test]
+  fn test_config_networks() {
+    let data = r#"
+[rpc]
+
+[[network]]
+name = "local"
+ss58_format = 42
+genesis_hash = "0x0102"
+symbol = "LOC"
+decimals = 18
+rpc_urls = ["ws://127.0.0.1:9944"]
+"#;
+    let config: Config = toml::from_str(data).unwrap();
+    let networks = config.networks();
+    let local = networks.get("local").unwrap();
+    assert_eq!(local.decimals, 18);
+    assert_eq!(networks.from_genesis_hash("0x0102"), Some(local));
+    assert!(networks.get("westend").is_some());
+    assert_eq!(config.get_urls(local), vec!["ws://127.0.0.1:9944".to_string()]);
+    assert_eq!(toml::from_str::<Config>(&config.to_string().unwrap()).unwrap(), config);
+  }
+
+  #[test]
+  fn test_config_default_network() {
+    let mut config = Config::parse_from_file("tests/fixtures/rpc_config.toml".as_ref()).unwrap();
+    assert_eq!(config.default_network(), None);
+    config.set_default_network("kusama".to_string());
+    let data = config.to_string().unwrap();
+    assert!(data.contains("[networks]"));
+    assert_eq!(toml::from_str::<Config>(&data).unwrap().default_network(), Some("kusama".to_string()));
   }
 
   #[test]
@@ -339,111 +867,162 @@ mod tests {
     let actual = Config::parse_from_file("/tmp/rpc_config.toml".as_ref()).unwrap();
     assert_eq!(config, actual)
   }
+
+  #[test]
+  fn test_config_default_wallet() {
+    let mut config = Config::parse_from_file("tests/fixtures/rpc_config.toml".as_ref()).unwrap();
+    assert_eq!(config.default_wallet(), None);
+    config.set_default_wallet("treasury".to_string());
+    assert_eq!(config.default_wallet(), Some("treasury".to_string()));
+
+    let data = config.to_string().unwrap();
+    assert!(data.contains("[wallet]"));
+    assert_eq!(toml::from_str::<Config>(&data).unwrap(), config);
+  }
+
+  #[test]
+  fn test_config_storage_backend() {
+    let config = Config::parse_from_file("tests/fixtures/rpc_config.toml".as_ref()).unwrap();
+    assert_eq!(config.storage_backend(), None);
+    let config: Config = toml::from_str("[rpc]\n[storage]\nbackend = \"sqlite\"\n").unwrap();
+    assert_eq!(config.storage_backend(), Some("sqlite".to_string()));
+  }
   
 
-  async fn setup_rpc() -> Rpc {
+  const ACCOUNTS: [&str; 3] = [
+    "1Zb1gY6xf1pzNhsYgbbrpnVSmtv6J8Gz44kS9334BLDxJan",
+    "13mmmB4jM9H7Ad3c6Hk5kDawDi2aXRsQ6eDVCbQqDJZ9khAH",
+    "1Qobp4G1snJPNWPz3onWpDVJGXtipBeF2EdLEdXT9aRRENe",
+  ];
+
+  fn replay_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("subwallet-rpc-{}-{}.json", name, std::process::id()))
+  }
+
+  /// A devnode in which `ACCOUNTS` are endowed, requests to it are recorded to `file` so that
+  /// each test can be repeated on `Rpc::replay`
+  async fn setup_rpc(file: &Path) -> Rpc {
     set_default_ss58_version(Ss58AddressFormat::PolkadotAccount);
-    Rpc::new("wss://rpc.polkadot.io".into()).await
+    let accounts: Vec<AccountId> = ACCOUNTS.iter().map(|addr| AccountId::from_ss58check(addr).unwrap()).collect();
+    let (url, _) = devnode::spawn(Chain::new(&accounts)).await;
+    Rpc::new(url).await.unwrap().record(file)
+  }
+
+  fn system_account_key(addr: &str) -> StorageKey {
+    let mut key = sp_core::twox_128(b"System").to_vec();
+    key.extend(sp_core::twox_128(b"Account").to_vec());
+    let account_key = AccountId::from_ss58check(addr).unwrap().encode();
+    key.extend(blake2_128(account_key.as_slice()).to_vec());
+    key.extend(account_key);
+    StorageKey(key)
   }
 
   #[tokio::test]
   async fn test_metadata() {
-    let rpc = setup_rpc().await;
-    // let hash = rpc.block_hash(Some(212894)).await.unwrap();
-    let meta = rpc.metadata(None).await;
-    assert!(meta.is_ok());
+    let file = replay_file("metadata");
+    let meta = setup_rpc(&file).await.metadata(None).await.unwrap();
+    let replayed = Rpc::replay(&file).unwrap().metadata(None).await.unwrap();
+    assert_eq!(replayed.encode(), meta.encode());
+    fs::remove_file(&file).unwrap();
   }
 
   #[tokio::test]
   async fn test_block_hash() {
-    let rpc = setup_rpc().await;
-    let hash = rpc.block_hash(Some(0)).await;
-    match hash {
-      Ok(v) => assert!(true, v),
-      Err(_) => unreachable!(),
-    }
+    let file = replay_file("block-hash");
+    let hash = setup_rpc(&file).await.block_hash(Some(0)).await.unwrap();
+    assert!(hash.is_some());
+    assert_eq!(Rpc::replay(&file).unwrap().block_hash(Some(0)).await.unwrap(), hash);
+    fs::remove_file(&file).unwrap();
   }
 
   #[tokio::test]
   async fn test_block() {
-    let rpc = setup_rpc().await;
+    let file = replay_file("block");
+    let rpc = setup_rpc(&file).await;
     let hash = rpc.block_hash(Some(0)).await.unwrap();
-    let block = rpc.block(hash).await;
-    match block {
-      Ok(Some(block)) => assert_eq!(block.block.header.number, 0),
-      _ => unreachable!()
-    }
+    let block = rpc.block(hash).await.unwrap().unwrap();
+    assert_eq!(block.block.header.number, 0);
+
+    let replay = Rpc::replay(&file).unwrap();
+    let hash = replay.block_hash(Some(0)).await.unwrap();
+    assert_eq!(replay.block(hash).await.unwrap(), Some(block));
+    fs::remove_file(&file).unwrap();
   }
 
   #[tokio::test]
   async fn test_query_storage() {
-    let rpc = setup_rpc().await;
-    let from = rpc.block_hash(None).await.unwrap().unwrap();
+    let file = replay_file("query-storage");
+    let rpc = setup_rpc(&file).await;
+    let from = rpc.block_hash(Some(0)).await.unwrap().unwrap();
     let to = rpc.block_hash(None).await.unwrap();
-    let mut key = sp_core::twox_128(b"System").to_vec();
-    key.extend(sp_core::twox_128(b"Account").to_vec());
-    let account_id = AccountId::from_ss58check("1Zb1gY6xf1pzNhsYgbbrpnVSmtv6J8Gz44kS9334BLDxJan").unwrap();
-
-    let account_key = account_id.encode();
-    let mut v = blake2_128(account_key.as_slice()).to_vec();
-    v.extend_from_slice(account_key.as_slice());
-    key.extend(v);
-
-    let keys = vec![sp_core::storage::StorageKey(key)];
-    let storage = rpc.query_storage(keys, from, to).await.unwrap();
+    let keys = vec![system_account_key(ACCOUNTS[0])];
+    let storage = rpc.query_storage(keys.clone(), from, to).await.unwrap();
 
     assert!(storage.len() > 0);
     let (_key, data) = storage[0].changes[0].clone();
-    let input = data.unwrap().0.clone();
+    let info = AccountInfo::decode(&mut &data.unwrap().0[..]).unwrap();
+    assert!(info.data.free > 0);
 
-    let info = AccountInfo::decode(&mut &input[..]);
-    assert!(info.is_ok());
+    let replayed = Rpc::replay(&file).unwrap().query_storage(keys, from, to).await.unwrap();
+    assert_eq!(replayed, storage);
+    fs::remove_file(&file).unwrap();
   }
 
   #[tokio::test]
   async fn test_get_storage() {
-    set_default_ss58_version(Ss58AddressFormat::PolkadotAccount);
-    let rpc = setup_rpc().await;
+    let file = replay_file("get-storage");
+    let rpc = setup_rpc(&file).await;
     let block_hash = rpc.block_hash(None).await.unwrap();
-
-    let mut key = sp_core::twox_128(b"System").to_vec();
-    key.extend(sp_core::twox_128(b"Account").to_vec());
-    let account_id = AccountId::from_ss58check("1Zb1gY6xf1pzNhsYgbbrpnVSmtv6J8Gz44kS9334BLDxJan").unwrap();
-
-    let account_key = account_id.encode();
-    let mut v = blake2_128(account_key.as_slice()).to_vec();
-    v.extend_from_slice(account_key.as_slice());
-    key.extend(v);
-
-    let k = sp_core::storage::StorageKey(key);
-    let storage = rpc.get_storage(k, block_hash).await.unwrap();
+    let storage = rpc.get_storage(system_account_key(ACCOUNTS[0]), block_hash).await.unwrap();
     assert!(storage.is_some());
+
+    let replayed = Rpc::replay(&file).unwrap().get_storage(system_account_key(ACCOUNTS[0]), block_hash).await.unwrap();
+    assert_eq!(replayed, storage);
+    fs::remove_file(&file).unwrap();
   }
 
   #[tokio::test]
   async fn test_get_balances() {
-    let rpc = setup_rpc().await;
-    let accounts = vec![
-      AccountId::from_ss58check("1Zb1gY6xf1pzNhsYgbbrpnVSmtv6J8Gz44kS9334BLDxJan").unwrap(),
-      AccountId::from_ss58check("13mmmB4jM9H7Ad3c6Hk5kDawDi2aXRsQ6eDVCbQqDJZ9khAH").unwrap(),
-    ];
-    let balances = rpc.get_balances(accounts).await.unwrap();
-    assert!(balances.len() > 0);
+    let file = replay_file("balances");
+    let accounts: Vec<AccountId> = ACCOUNTS[..2].iter().map(|addr| AccountId::from_ss58check(addr).unwrap()).collect();
+    let balances = setup_rpc(&file).await.get_balances(accounts.clone()).await.unwrap();
+    assert_eq!(balances.len(), 2);
+    assert!(balances.iter().all(|(_, balance)| *balance > 0));
+
+    assert_eq!(Rpc::replay(&file).unwrap().get_balances(accounts).await.unwrap(), balances);
+    fs::remove_file(&file).unwrap();
   }
 
   #[tokio::test]
   async fn test_get_account_info() {
-    let rpc = setup_rpc().await;
-    let id = AccountId::from_ss58check("1Qobp4G1snJPNWPz3onWpDVJGXtipBeF2EdLEdXT9aRRENe").unwrap();
+    let file = replay_file("account-info");
+    let id = AccountId::from_ss58check(ACCOUNTS[2]).unwrap();
+    let info = setup_rpc(&file).await.get_account_info(id.clone()).await.unwrap();
+    assert!(info.data.free > 0);
 
-    let info = rpc.get_account_info(id).await.unwrap();
-    assert!(info.refcount > 0);
+    assert_eq!(Rpc::replay(&file).unwrap().get_account_info(id).await.unwrap(), info);
+    fs::remove_file(&file).unwrap();
   }
 
   #[tokio::test]
-  async fn submit_extrinsic_should_fail_since_transfer_is_disabled() {
-    let rpc = setup_rpc().await;
-    // let meta = rpc.metadata(None).await;
+  async fn test_missing_data_is_an_error() {
+    let file = replay_file("missing");
+    let account = AccountId::default();
+    let exchanges = serde_json::json!([
+      { "method": "chain_getBlockHash", "params": [0], "result": null },
+      { "method": "state_queryStorageAt", "params": [[account_key(&account)], null], "result": [] },
+    ]);
+    fs::write(&file, exchanges.to_string()).unwrap();
+    let rpc = Rpc::replay(&file).unwrap();
+    assert!(rpc.genesis_hash().await.unwrap_err().to_string().contains("no genesis block"));
+    assert!(rpc.get_balances(vec![account]).await.is_err());
+    fs::remove_file(&file).unwrap();
+  }
+
+  #[tokio::test]
+  async fn submit_extrinsic_should_fail_since_it_is_signed_for_another_chain() {
+    let file = replay_file("submit");
+    let rpc = setup_rpc(&file).await;
     let from_address = Address::generate::<Ed25519>();
     let to_address = Address::generate::<Ed25519>();
     let to_account_id = AccountId::from_ss58check(&to_address.addr).unwrap();
@@ -451,8 +1030,12 @@ mod tests {
     let signer = from_address.into_pair::<Ed25519>();
     let genesis_hash = crate::networks::POLKADOT_GENESIS_HASH;
     let genesis_hash = Hash::from_str(&genesis_hash[2..]).unwrap();
-    let xt = crate::transfer::make_extrinsic::<Ed25519>(call, 0, signer, genesis_hash).unwrap();
-    let result = rpc.submit_extrinsic(xt).await;
+    let xt = crate::transfer::make_extrinsic::<Ed25519>(call, 0, signer, genesis_hash, &RuntimeVersion::compiled()).unwrap();
+    let result = rpc.submit_extrinsic(xt.clone()).await;
     assert!(result.is_err());
+
+    // the error is replayed
+    assert!(Rpc::replay(&file).unwrap().submit_extrinsic(xt).await.is_err());
+    fs::remove_file(&file).unwrap();
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,218 @@
+use std::fs;
+use bip39::{ Language, Mnemonic };
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroizing;
+
+/// Length of a word list chunk, 32 bytes are encoded as 24 words
+const WORDS_CHUNK_LENGTH: usize = 32;
+
+/// Multiplication in GF(2^8) with the AES polynomial x^8 + x^4 + x^3 + x + 1, it runs in constant time
+fn mul(mut a: u8, mut b: u8) -> u8 {
+  let mut p = 0u8;
+  for _ in 0..8 {
+    p ^= a & (b & 1).wrapping_neg();
+    let carry = (a >> 7).wrapping_neg();
+    a = (a << 1) ^ (0x1b & carry);
+    b >>= 1;
+  }
+  p
+}
+
+/// Multiplicative inverse, a^254 = a^-1
+fn inv(a: u8) -> u8 {
+  let mut result = 1u8;
+  let mut base = a;
+  let mut exp = 254u8;
+  while exp > 0 {
+    if exp & 1 == 1 {
+      result = mul(result, base);
+    }
+    base = mul(base, base);
+    exp >>= 1;
+  }
+  result
+}
+
+/// One share of a secret, `index` is the x coordinate of the polynomials
+#[derive(Clone, Debug, PartialEq)]
+pub struct Share {
+  pub index: u8,
+  pub threshold: u8,
+  pub value: Zeroizing<Vec<u8>>,
+}
+
+impl Share {
+  /// `<index>/<threshold>` followed by the BIP39 words of every 32 bytes chunk of value
+  pub fn to_words(&self) -> Result<String, ()> {
+    let mut words = vec![format!("{}/{}", self.index, self.threshold)];
+    for chunk in self.value.chunks(WORDS_CHUNK_LENGTH) {
+      let mnemonic = Mnemonic::from_entropy(chunk, Language::English).map_err(|_| () )?;
+      words.push(mnemonic.phrase().to_string());
+    }
+    Ok(words.join(" "))
+  }
+
+  pub fn from_words(words: &str) -> Result<Self, ()> {
+    let mut words = words.split_whitespace();
+    let header: Vec<&str> = words.next().ok_or(())?.split('/').collect();
+    if header.len() != 2 {
+      return Err(())
+    }
+    let index = header[0].parse::<u8>().map_err(|_| () )?;
+    let threshold = header[1].parse::<u8>().map_err(|_| () )?;
+
+    let words: Vec<&str> = words.collect();
+    let mut value = Zeroizing::new(vec![]);
+    for chunk in words.chunks(24) {
+      let phrase = Zeroizing::new(chunk.join(" "));
+      let mnemonic = Mnemonic::from_phrase(&phrase, Language::English).map_err(|_| () )?;
+      value.extend_from_slice(mnemonic.entropy());
+    }
+    if index == 0 || value.is_empty() {
+      return Err(())
+    }
+    Ok(Self { index, threshold, value })
+  }
+}
+
+/// Split `secret` into `shares` shares, any `threshold` of them can rebuild the secret
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> Result<Vec<Share>, ()> {
+  if threshold < 2 || threshold > shares || secret.is_empty() {
+    return Err(())
+  }
+
+  let mut rng = rand::thread_rng();
+  let mut result: Vec<Share> = (1..=shares).map(|index| Share {
+    index: index,
+    threshold: threshold,
+    value: Zeroizing::new(Vec::with_capacity(secret.len())),
+  }).collect();
+
+  // random polynomial of degree `threshold - 1` for each byte, the constant term is the secret byte
+  let mut coefficients = Zeroizing::new(vec![0u8; threshold as usize]);
+  for byte in secret {
+    coefficients[0] = *byte;
+    rng.fill(&mut coefficients[1..]);
+    for share in result.iter_mut() {
+      let mut y = 0u8;
+      for c in coefficients.iter().rev() {
+        y = mul(y, share.index) ^ c;
+      }
+      share.value.push(y);
+    }
+  }
+  Ok(result)
+}
+
+/// Rebuild the secret by Lagrange interpolation at x = 0
+pub fn combine(shares: &[Share]) -> Result<Zeroizing<Vec<u8>>, ()> {
+  let first = shares.first().ok_or(())?;
+  if shares.len() < first.threshold as usize {
+    return Err(())
+  }
+  for (i, share) in shares.iter().enumerate() {
+    if share.index == 0 || share.threshold != first.threshold || share.value.len() != first.value.len() {
+      return Err(())
+    }
+    if shares[..i].iter().any(|s| s.index == share.index) {
+      return Err(())
+    }
+  }
+
+  let mut secret = Zeroizing::new(vec![0u8; first.value.len()]);
+  for share in shares {
+    // basis polynomial at x = 0, subtraction is xor in GF(2^8)
+    let mut basis = 1u8;
+    for other in shares.iter().filter(|s| s.index != share.index) {
+      basis = mul(basis, mul(other.index, inv(other.index ^ share.index)));
+    }
+    for (s, y) in secret.iter_mut().zip(share.value.iter()) {
+      *s ^= mul(basis, *y);
+    }
+  }
+  Ok(secret)
+}
+
+/// The json file of one share, it keeps the public information needed to rebuild the address
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShareFile {
+  pub address: String,
+  pub crypto_type: String,
+  pub path: String,
+  pub index: u8,
+  pub threshold: u8,
+  /// Words of the share, see `Share::to_words`
+  pub share: String,
+}
+
+impl ShareFile {
+  pub fn parse_from_file(path: String) -> Result<Self, ()> {
+    let data = fs::read_to_string(path).map_err(|_| () )?;
+    serde_json::from_str(&data).map_err(|_| () )
+  }
+
+  pub fn to_share(&self) -> Result<Share, ()> {
+    let share = Share::from_words(&self.share)?;
+    if share.index != self.index || share.threshold != self.threshold {
+      return Err(())
+    }
+    Ok(share)
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(&self).unwrap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_gf256() {
+    assert_eq!(mul(0x57, 0x83), 0xc1);
+    assert_eq!(mul(0x57, 0x13), 0xfe);
+    for a in 1..=255u8 {
+      assert_eq!(mul(a, inv(a)), 1);
+    }
+  }
+
+  #[test]
+  fn test_split_and_combine() {
+    let secret = [7u8; 32];
+    let shares = split(&secret, 3, 5).unwrap();
+    assert_eq!(shares.len(), 5);
+    assert!(shares.iter().all(|s| s.value.len() == 32 && s.threshold == 3));
+
+    assert_eq!(&combine(&shares[..3]).unwrap()[..], &secret[..]);
+    assert_eq!(&combine(&shares[2..]).unwrap()[..], &secret[..]);
+    let picked = vec![shares[4].clone(), shares[0].clone(), shares[2].clone()];
+    assert_eq!(&combine(&picked).unwrap()[..], &secret[..]);
+    assert_eq!(&combine(&shares).unwrap()[..], &secret[..]);
+
+    assert!(combine(&shares[..2]).is_err());
+    let duplicated = vec![shares[0].clone(), shares[0].clone(), shares[1].clone()];
+    assert!(combine(&duplicated).is_err());
+  }
+
+  #[test]
+  fn test_split_with_invalid_threshold() {
+    assert!(split(&[1u8; 32], 1, 5).is_err());
+    assert!(split(&[1u8; 32], 6, 5).is_err());
+    assert!(split(&[], 3, 5).is_err());
+  }
+
+  #[test]
+  fn test_share_words() {
+    let secret = [9u8; 64];
+    let shares = split(&secret, 2, 3).unwrap();
+    let words = shares[1].to_words().unwrap();
+    assert!(words.starts_with("2/2 "));
+    assert_eq!(words.split_whitespace().count(), 49);
+    assert_eq!(Share::from_words(&words).unwrap(), shares[1]);
+
+    assert!(Share::from_words("2/2 abandon abandon").is_err());
+    assert!(Share::from_words("0/2").is_err());
+  }
+}
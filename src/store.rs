@@ -1,6 +1,7 @@
 use serde::{Serialize, Deserialize};
-use rustbreak::{FileDatabase};
-use rustbreak::deser::Bincode;
+use std::path::Path;
+use crate::migration::{ Header, Schema };
+use crate::backend::{ self, Backend };
 
 // use std::path::PathBuf;
 // use std::fs;
@@ -34,8 +35,10 @@ impl Extrinsic {
   }
 }
 
-#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct Extrinsics {
+  /// It must be the first field, see `migration`
+  header: Header,
   addr: String,
   xts: Vec<Extrinsic>,
   pub scanned_at: u32,
@@ -43,9 +46,12 @@ pub struct Extrinsics {
 
 impl Extrinsics {
   pub fn new(addr: String) -> Self {
-    let mut extrinsics = Self::default();
-    extrinsics.addr = addr;
-    extrinsics
+    Self {
+      header: Header::new(<Self as Schema>::VERSION),
+      addr: addr,
+      xts: vec![],
+      scanned_at: 0,
+    }
   }
 
   pub fn add(&mut self, xt: Extrinsic) {
@@ -61,17 +67,14 @@ impl Extrinsics {
 }
 
 
-pub struct FileStore(FileDatabase<Extrinsics, Bincode>);
+pub struct FileStore(Box<dyn Backend<Extrinsics>>);
 
 impl FileStore {
 
-  pub fn get(addr: &str) -> Self {
-    let mut file = dirs::home_dir().unwrap();
-    file.push(".subwallet");
-    file.push(format!("xt-{}", addr).as_str());
-
+  /// Extrinsics of `addr` in data path
+  pub fn open(path: &Path, addr: &str) -> Self {
     let backend = Extrinsics::new(addr.to_owned());
-    let db = FileDatabase::<Extrinsics, Bincode>::load_from_path_or(file, backend).expect("Failed to initialize file database.");
+    let db = backend::open(path, &format!("xt-{}", addr), backend, 0).expect("Failed to initialize file database.");
     Self(db)
   }
 
@@ -96,24 +99,16 @@ impl FileStore {
   //  Self(db)
   // }
 
-  pub fn load(&self) {
-    let _ = self.0.load();
-  }
-
   pub fn save(&self, tx: Extrinsic) {
-    self.load();
     self.0.write(|backend| {
       backend.add(tx)
     }).expect("Failed to write Extrinsic");
-    self.0.save().expect("Failed to save");
   }
 
   pub fn update(&self, scanned_at: u32) {
-    self.load();
     self.0.write(|backend| {
       backend.scanned_at = scanned_at
     }).expect("Failed to update Extrinsics");
-    self.0.save().expect("Failed to save");
   }
 
   // pub fn read(&self, tx_hash: &str) -> Option<Extrinsic> {
@@ -128,13 +123,10 @@ impl FileStore {
 
 
   pub fn read(&self) -> Extrinsics {
-    self.load();
-    self.0.borrow_data().expect("Failed to read data").clone()
+    self.0.read().expect("Failed to read data")
   }
 
   pub fn read_all(&self) -> Vec<Extrinsic> {
-    self.load();
-    let backend = self.0.borrow_data().expect("Failed to read data");
-    backend.xts.clone()
+    self.read().xts
   }
 }
\ No newline at end of file
@@ -4,9 +4,10 @@ use futures::executor::ThreadPool;
 use futures::future;
 
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{ Arc, Mutex };
 use std::{thread, time};
 use std::collections::BTreeMap;
+use std::path::{ Path, PathBuf };
 
 use codec::{ Decode, Compact };
 use indicatif::{ ProgressBar, ProgressStyle };
@@ -17,152 +18,151 @@ use frame_system::{ Phase, RawEvent };
 use sp_core::crypto::{ Ss58Codec };
 
 use crate::rpc::*;
-use crate::error::{ Result, Error };
+use crate::error::Result;
 use crate::store::*;
 use frame_support::traits::{GetCallMetadata};
 // use frame_support::dispatch::{Callable, CallableCallFor};
 
 #[derive(Clone)]
 pub struct Scanner {
-  url: String,
+  /// Data path of extrinsics stores
+  path: PathBuf,
+  /// It fails over to another endpoint of the network on transport failures
+  rpc: Rpc,
   accounts: Vec<AccountId>,
   cursor: Arc<AtomicU64>,
   step: u64,
   tip_number: u64,
   tx: UnboundedSender<()>,
+  /// The first error which stops scanning, it is returned by `run`
+  error: Arc<Mutex<Option<String>>>,
 }
 
 impl Scanner {
-  pub fn new(url: String, accounts: Vec<AccountId>, tx: UnboundedSender<()>) -> Self {
+  pub fn new(path: PathBuf, rpc: Rpc, accounts: Vec<AccountId>, tx: UnboundedSender<()>) -> Self {
     Self {
-      url,
+      path,
+      rpc,
       accounts,
       cursor: Arc::new(AtomicU64::new(0)),
       step: SCAN_STEP,
       tip_number: 0,
       tx: tx,
+      error: Arc::new(Mutex::new(None)),
     }
   }
 
   fn touch(&self, n: u32) {
     for account in self.accounts.iter() {
       let addr = account.to_ss58check();
-      let store = FileStore::get(addr.as_str());
+      let store = FileStore::open(&self.path, addr.as_str());
       store.update(n);
     }
   }
 
   async fn scan(self) {
-    let mut finished = true;
-    let mut pos: u32 = 0;
-    let rpc = Rpc::new(self.url.clone()).await;
+    if let Err(err) = self.scan_ranges().await {
+      self.error.lock().unwrap().get_or_insert(err.to_string());
+      // the other workers stop after their current range
+      self.cursor.store(self.tip_number + 1, Ordering::SeqCst);
+    }
+    drop(self.tx);
+  }
+
+  async fn scan_ranges(&self) -> Result<()> {
     loop {
-      let now = self.cursor.load(Ordering::SeqCst);
-      if now > self.tip_number {
-        break;
+      let start = self.cursor.fetch_add(self.step, Ordering::SeqCst);
+      if start > self.tip_number {
+        return Ok(())
       }
+      let end = (start + self.step - 1).min(self.tip_number);
+      self.scan_range(start as u32, end as u32).await?;
+      self.touch(end as u32);
+    }
+  }
 
-      let (start, end) = if finished {
-        let p = self.cursor.fetch_add(self.step, Ordering::SeqCst);
-        let start: u32 = p as u32;
-        let end: u32 = p as u32 + self.step as u32 - 1;
-        (start, end)
-      } else {
-        (pos, pos + self.step as u32 - 1)
-      };
-
-      let hashes = future::join(rpc.block_hash(Some(start)), rpc.block_hash(Some(end))).await;
-      let (start_hash, end_hash) = match hashes {
-        (Ok(Some(start)), Ok(end)) => ( start, end ),
-        _ => {
-          let rpc = Rpc::new(rpc.url.clone()).await;
-          finished = false;
-          pos = start;
-          continue
+  /// A range is scanned again after the backoff of the retry policy if a request fails in transport,
+  /// other errors stop scanning
+  async fn scan_range(&self, start: u32, end: u32) -> Result<()> {
+    let policy = self.rpc.policy();
+    let mut attempt = 0;
+    loop {
+      match self.try_scan_range(start, end).await {
+        Err(err) if err.is_transport() && attempt < policy.retries => {
+          policy.wait(attempt).await;
+          attempt += 1;
         },
-      };
+        result => return result,
+      }
+    }
+  }
 
-      let mut key = sp_core::twox_128(b"System").to_vec();
-      key.extend(sp_core::twox_128(b"Events").to_vec());
-      let keys = vec![sp_core::storage::StorageKey(key)];
-      let storage = match rpc.query_storage(keys, start_hash, end_hash).await {
-        Ok(storage) => storage,
-        Err(err) => match err {
-          Error::Rpc(..) | Error::WsHandshake(..) => {
-            let rpc = Rpc::new(rpc.url.clone()).await;
-            finished = false;
-            pos = start;
-            continue;
-          },
-          _ => continue,
-        },
-      };
+  async fn try_scan_range(&self, start: u32, end: u32) -> Result<()> {
+    let (start_hash, end_hash) = future::try_join(self.rpc.block_hash(Some(start)), self.rpc.block_hash(Some(end))).await?;
+    let start_hash = start_hash.ok_or(format!("Block {} is not found", start))?;
 
-      for changeset in storage {
-        let (_k, data) = changeset.changes[0].clone();
-        if let Some(v) = data {
-          let mut input = v.0.as_slice();
-          let compact_len = <Compact<u32>>::decode(&mut input).unwrap();
-          let len = compact_len.0 as usize;
-
-          let mut records_with_idx: BTreeMap<usize, Vec<EventRecord>> = BTreeMap::new();
-          for _i in 0..len {
-            let record = match EventRecord::decode(&mut input) {
-              Ok(v) => v,
-              Err(_err) => {
-                // TODO process decode error
-                continue
-              },
-            };
+    let mut key = sp_core::twox_128(b"System").to_vec();
+    key.extend(sp_core::twox_128(b"Events").to_vec());
+    let keys = vec![sp_core::storage::StorageKey(key)];
+    let storage = self.rpc.query_storage(keys, start_hash, end_hash).await?;
 
-            let index: usize = match record.phase {
-              Phase::ApplyExtrinsic(i) => i as usize,
-              _ => continue,
-            };
+    for changeset in storage {
+      let (_k, data) = changeset.changes[0].clone();
+      if let Some(v) = data {
+        let mut input = v.0.as_slice();
+        let compact_len = <Compact<u32>>::decode(&mut input)?;
+        let len = compact_len.0 as usize;
 
-            let maybe: bool = match record.event {
-              Event::system(RawEvent::ExtrinsicFailed(..)) => true,
-              // Event::utility(..) => true,
-              _ => false,
-            };
+        let mut records_with_idx: BTreeMap<usize, Vec<EventRecord>> = BTreeMap::new();
+        for _i in 0..len {
+          let record = match EventRecord::decode(&mut input) {
+            Ok(v) => v,
+            Err(_err) => {
+              // TODO process decode error
+              continue
+            },
+          };
+
+          let index: usize = match record.phase {
+            Phase::ApplyExtrinsic(i) => i as usize,
+            _ => continue,
+          };
+
+          let maybe: bool = match record.event {
+            Event::system(RawEvent::ExtrinsicFailed(..)) => true,
+            // Event::utility(..) => true,
+            _ => false,
+          };
 
-            let event_string = format!("{:?}", record.event);
-            let filtered_accouts = self.accounts.iter().filter(|id| {
-              let target = format!("{:?}", id);
-              event_string.as_str().contains(target.as_str())
-            }).map(|id| id.clone()).collect::<Vec<AccountId>>();
-
-            if maybe || filtered_accouts.len() > 0 {
-              if records_with_idx.get(&index).is_some() {
-                if let Some(v) = records_with_idx.get_mut(&index) {
-                  v.push(record.clone());
-                }
-              } else {
-                records_with_idx.insert(index, vec![record.clone()]);
+          let event_string = format!("{:?}", record.event);
+          let filtered_accouts = self.accounts.iter().filter(|id| {
+            let target = format!("{:?}", id);
+            event_string.as_str().contains(target.as_str())
+          }).map(|id| id.clone()).collect::<Vec<AccountId>>();
+
+          if maybe || filtered_accouts.len() > 0 {
+            if records_with_idx.get(&index).is_some() {
+              if let Some(v) = records_with_idx.get_mut(&index) {
+                v.push(record.clone());
               }
+            } else {
+              records_with_idx.insert(index, vec![record.clone()]);
             }
           }
+        }
 
-          if records_with_idx.len() > 0 {
-            let block_hash = changeset.block;
-            let block = match rpc.block(Some(block_hash)).await {
-              Ok(signed) => signed.unwrap(),
-              Err(_err) => {
-                continue
-              }
-            };
-            Self::process(block_hash, block, records_with_idx, self.accounts.clone());
-          }
+        if records_with_idx.len() > 0 {
+          let block_hash = changeset.block;
+          let block = self.rpc.block(Some(block_hash)).await?.ok_or(format!("Block {:#x} is not found", block_hash))?;
+          Self::process(&self.path, block_hash, block, records_with_idx, self.accounts.clone());
         }
       }
-
-      self.touch(end);
-      finished = true;
     }
-    drop(self.tx);
+    Ok(())
   }
 
   fn process(
+    path: &Path,
     block_hash: Hash, 
     block: SignedBlock, 
     event_records: BTreeMap<usize, Vec<EventRecord>>,
@@ -211,7 +211,7 @@ impl Scanner {
               call: data.function_name.to_string(),
             };
             let addr = account.clone().to_ss58check();
-            let store = FileStore::get(addr.as_str());
+            let store = FileStore::open(path, addr.as_str());
             store.save(extrinsic);
           }
         }
@@ -221,15 +221,15 @@ impl Scanner {
 }
 
 
-pub async fn run(url: String, accounts: Vec<AccountId>) -> Result<()> {
-  let threads_size = num_cpus::get() / 2;
-  let cursors: Vec<u32> = accounts.iter().map(|id| FileStore::get(id.to_ss58check().as_str()).read().scanned_at).collect();
+/// Scan the blocks from the lowest `scanned_at` of `accounts` to the best block, half of the CPUs are used
+pub async fn run(path: PathBuf, rpc: Rpc, accounts: Vec<AccountId>) -> Result<()> {
+  let threads_size = (num_cpus::get() / 2).max(1);
+  let cursors: Vec<u32> = accounts.iter().map(|id| FileStore::open(&path, id.to_ss58check().as_str()).read().scanned_at).collect();
   let start_number = cursors.iter().min().unwrap_or(&0u32).clone();
-  let rpc = Arc::new(Rpc::new(url.clone()).await);
-  let tip_header = rpc.header(None).await?.unwrap();
+  let tip_header = rpc.header(None).await?.ok_or("The best block is not found")?;
   let tip_number = tip_header.number as u64;
   let (tx, mut rx) = unbounded();
-  let mut scanner = Scanner::new(url.clone(), accounts, tx);
+  let mut scanner = Scanner::new(path, rpc, accounts, tx);
   scanner.cursor = Arc::new(AtomicU64::new(start_number as u64));
   scanner.tip_number = tip_number;
 
@@ -257,6 +257,51 @@ pub async fn run(url: String, accounts: Vec<AccountId>) -> Result<()> {
       break;
     }
   }
-  Ok(())
+  match scanner.error.lock().unwrap().take() {
+    Some(err) => Err(err.into()),
+    None => Ok(()),
+  }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crypto::Ed25519;
+  use crate::devnode::{ self, Chain };
+  use crate::transfer;
+  use codec::Encode;
+  use runtime::{ BalancesCall, Call };
+  use sp_core::{ ed25519, Pair };
+
+  #[tokio::test]
+  async fn test_run() {
+    let alice = ed25519::Pair::from_string("//Alice", None).unwrap();
+    let (from, to) = (AccountId::from(alice.public()), AccountId::from(ed25519::Pair::from_string("//Bob", None).unwrap().public()));
+    let mut chain = Chain::new(&[from.clone()]);
+    let call = Call::Balances(BalancesCall::transfer(to.clone(), 100));
+    let xt = transfer::make_extrinsic::<Ed25519>(call, 0, alice, chain.genesis_hash(), &RuntimeVersion::compiled()).unwrap();
+    chain.submit(&xt.encode()).unwrap();
+    let (url, _) = devnode::spawn(chain).await;
+
+    let path = std::env::temp_dir().join(format!("subwallet-sync-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    run(path.clone(), Rpc::new(url).await.unwrap(), vec![to.clone()]).await.unwrap();
+
+    let store = FileStore::open(&path, &to.to_ss58check());
+    assert_eq!(store.read().scanned_at, 1);
+    let xts = store.read_all();
+    assert_eq!(xts.len(), 1);
+    assert_eq!((xts[0].block_number, xts[0].index), (1, 0));
+    assert_eq!((xts[0].module.as_str(), xts[0].call.as_str(), xts[0].status.as_str()), ("Balances", "transfer", "success"));
+    assert_eq!(xts[0].signer, Some(from.to_ss58check()));
+  }
+
+  #[tokio::test]
+  async fn test_run_error() {
+    let path = std::env::temp_dir().join(format!("subwallet-sync-error-{}", std::process::id()));
+    let replay = path.with_extension("json");
+    std::fs::write(&replay, "[]").unwrap();
+    // nothing is recorded, so the best block can not be read
+    assert!(run(path, Rpc::replay(&replay).unwrap(), vec![AccountId::default()]).await.is_err());
+  }
+}
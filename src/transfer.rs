@@ -1,9 +1,14 @@
 
-use runtime::{Call, Runtime, SignedPayload, UncheckedExtrinsic, VERSION, };
+use runtime::{Call, Runtime, SignedPayload, UncheckedExtrinsic, };
 use crate::primitives::{Hash, Nonce as Index, Balance, Signature, AccountPublic };
 use crate::crypto::{ Crypto, Pair, ed25519, sr25519, ecdsa, Ss58Codec };
 use crate::error::Result;
-use codec::{ Encode };
+use crate::rpc::RuntimeVersion;
+use codec::{ Encode, Decode };
+use frame_metadata::{
+  DecodeDifferent, DFnA, ErrorMetadata, EventMetadata, FnEncode, FunctionMetadata, ModuleConstantMetadata, ODFnA,
+  RuntimeMetadata, RuntimeMetadataPrefixed, StorageMetadata, META_RESERVED,
+};
 use sp_runtime::generic::Era;
 use sp_runtime::traits::IdentifyAccount;
 
@@ -35,11 +40,24 @@ pub fn make_extrinsic<C: Crypto>(
   nonce: Index,
   signer: C::Pair,
   genesis_hash: Hash,
+  version: &RuntimeVersion,
 ) -> Result<UncheckedExtrinsic> where 
   SignatureOf<C>: SignatureT,
   PublicOf<C>: PublicT,
 {
+  let raw_payload = make_payload(function, nonce, genesis_hash, version);
+  let signature = raw_payload.using_encoded(|payload| signer.sign(payload));
+  make_signed_extrinsic::<C>(raw_payload, signer.public(), signature)
+}
 
+/// Payload to be signed, the bytes to sign are `raw_payload.using_encoded(|payload| payload.to_vec())`.
+/// `version` is the runtime version of the node, it changes with runtime upgrades.
+pub fn make_payload(
+  function: Call,
+  nonce: Index,
+  genesis_hash: Hash,
+  version: &RuntimeVersion,
+) -> SignedPayload {
   let extra = |i: Index, f: Balance| {
     (
       frame_system::CheckSpecVersion::<Runtime>::new(),
@@ -55,12 +73,12 @@ pub fn make_extrinsic<C: Crypto>(
       runtime_common::claims::PrevalidateAttests::<Runtime>::new(),
     )
   };
-  let raw_payload = SignedPayload::from_raw(
+  SignedPayload::from_raw(
     function,
     extra(nonce, 0),
     (
-      VERSION.spec_version,
-      VERSION.transaction_version,
+      version.spec_version,
+      version.transaction_version,
       genesis_hash,
       genesis_hash,
       (),
@@ -71,9 +89,20 @@ pub fn make_extrinsic<C: Crypto>(
       // (),
       (),
     ),
-  );
-  let signature = raw_payload.using_encoded(|payload| signer.sign(payload)).into_runtime();
-  let signer_account_id = signer.public().into_runtime().into_account().into();
+  )
+}
+
+/// Make extrinsic with the signature of payload, e.g. the signature is made by unlock agent
+pub fn make_signed_extrinsic<C: Crypto>(
+  raw_payload: SignedPayload,
+  public: PublicOf<C>,
+  signature: SignatureOf<C>,
+) -> Result<UncheckedExtrinsic> where 
+  SignatureOf<C>: SignatureT,
+  PublicOf<C>: PublicT,
+{
+  let signature = signature.into_runtime();
+  let signer_account_id = public.into_runtime().into_account().into();
   let (function, extra, _) = raw_payload.deconstruct();
 
   let xt = UncheckedExtrinsic::new_signed(
@@ -85,6 +114,101 @@ pub fn make_extrinsic<C: Crypto>(
   Ok(xt)
 }
 
+/// Index of module and call, and the types of arguments, they decide how a call is encoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallSignature {
+  pub module_index: u8,
+  pub call_index: u8,
+  pub arguments: Vec<String>,
+}
+
+/// Module of V12 metadata, it is the module of V11 with the index used in the encoding of its calls,
+/// the fields before the index are decoded only to skip them
+#[derive(Decode)]
+struct ModuleMetadataV12 {
+  name: DecodeDifferent<&'static str, String>,
+  _storage: Option<DecodeDifferent<FnEncode<StorageMetadata>, StorageMetadata>>,
+  calls: ODFnA<FunctionMetadata>,
+  _event: ODFnA<EventMetadata>,
+  _constants: DFnA<ModuleConstantMetadata>,
+  _errors: DFnA<ErrorMetadata>,
+  index: u8,
+}
+
+/// V12 metadata, only the modules are decoded
+#[derive(Decode)]
+struct RuntimeMetadataV12 {
+  modules: Vec<ModuleMetadataV12>,
+}
+
+fn decoded<B, O>(value: &DecodeDifferent<B, O>) -> Option<&O> {
+  match value {
+    DecodeDifferent::Decoded(value) => Some(value),
+    DecodeDifferent::Encode(_) => None,
+  }
+}
+
+/// Signature of `function` in the calls of `modules`, they are given as index, name and calls
+fn find_call(modules: Vec<(u8, &str, &[FunctionMetadata])>, module: &str, function: &str) -> Option<CallSignature> {
+  let (module_index, _, calls) = modules.into_iter().find(|(_, name, _)| *name == module)?;
+  let (call_index, call) = calls.iter().enumerate()
+    .find(|(_, call)| decoded(&call.name).map_or(false, |name| name.as_str() == function))?;
+  let arguments = decoded(&call.arguments)?.iter()
+    .filter_map(|argument| decoded(&argument.ty).cloned())
+    .collect();
+  Some(CallSignature {
+    module_index,
+    call_index: call_index as u8,
+    arguments,
+  })
+}
+
+/// Signature of `module::function` in SCALE encoded metadata, `None` if it is not found.
+/// V11 and V12 metadata are supported, other versions are an error.
+pub fn call_signature(metadata: &[u8], module: &str, function: &str) -> Result<Option<CallSignature>> {
+  let version = metadata.get(META_RESERVED.encoded_size()).copied().ok_or("The metadata is empty")?;
+  match version {
+    11 => {
+      let metadata = match RuntimeMetadataPrefixed::decode(&mut &metadata[..])?.1 {
+        RuntimeMetadata::V11(metadata) => metadata,
+        _ => unreachable!(),
+      };
+      let modules = match decoded(&metadata.modules) {
+        Some(modules) => modules,
+        None => return Ok(None),
+      };
+      // modules without calls have no index, the index is their position among modules with calls
+      let modules = modules.iter()
+        .filter_map(|m| Some((decoded(&m.name)?.as_str(), decoded(m.calls.as_ref()?)?.as_slice())))
+        .enumerate()
+        .map(|(index, (name, calls))| (index as u8, name, calls))
+        .collect();
+      Ok(find_call(modules, module, function))
+    },
+    12 => {
+      let metadata = RuntimeMetadataV12::decode(&mut &metadata[META_RESERVED.encoded_size() + 1..])?;
+      let modules = metadata.modules.iter()
+        .filter_map(|m| Some((m.index, decoded(&m.name)?.as_str(), decoded(m.calls.as_ref()?)?.as_slice())))
+        .collect();
+      Ok(find_call(modules, module, function))
+    },
+    version => Err(format!("Metadata version {} is not supported", version).into()),
+  }
+}
+
+/// Warning if the compiled runtime encodes `module::function` differently from `node`, the SCALE encoded metadata of the node
+pub fn check_call_encoding(node: &[u8], module: &str, function: &str) -> Option<String> {
+  let compiled = call_signature(&Runtime::metadata().encode(), module, function).ok()??;
+  match call_signature(node, module, function) {
+    Ok(Some(ref signature)) if *signature == compiled => None,
+    Ok(Some(signature)) => Some(format!(
+      "`{}::{}` of the node is {:?}, but the compiled runtime encodes it as {:?}, the transaction may be rejected or do something else, please upgrade subwallet",
+      module, function, signature, compiled,
+    )),
+    Ok(None) => Some(format!("`{}::{}` is not found in the metadata of the node", module, function)),
+    Err(err) => Some(format!("{}, the encoding of `{}::{}` is not checked against the node", err, module, function)),
+  }
+}
 
 #[cfg(test)]
 mod tests {
@@ -95,6 +219,7 @@ mod tests {
   use std::str::FromStr;
   use super::*;
   use codec::{Encode};
+  use frame_metadata::ModuleMetadata;
   use sp_runtime::traits::Verify;
 
   #[test]
@@ -106,12 +231,13 @@ mod tests {
     let signer = from_address.into_pair::<Sr25519>();
     let genesis_hash = crate::networks::POLKADOT_GENESIS_HASH;
     let genesis_hash = Hash::from_str(&genesis_hash[2..]).unwrap();
-    let xt = make_extrinsic::<Sr25519>(call.clone(), 0, signer, genesis_hash).unwrap();
+    let version = RuntimeVersion::compiled();
+    let xt = make_extrinsic::<Sr25519>(call.clone(), 0, signer, genesis_hash, &version).unwrap();
 
     let (addr, signature, extra) = xt.signature.clone().unwrap();
     let addiational = (
-      VERSION.spec_version,
-      VERSION.transaction_version,
+      version.spec_version,
+      version.transaction_version,
       genesis_hash,
       genesis_hash,
       (),
@@ -125,4 +251,115 @@ mod tests {
     let raw_payload = SignedPayload::from_raw(call, extra, addiational);
     assert!(raw_payload.using_encoded(|payload| signature.verify(payload, &addr)));
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_make_payload_uses_given_version() {
+    let call = Call::Balances(BalancesCall::transfer(AccountId::default(), 100));
+    let version = RuntimeVersion {
+      spec_version: runtime::VERSION.spec_version + 1,
+      transaction_version: runtime::VERSION.transaction_version + 1,
+      ..RuntimeVersion::compiled()
+    };
+    let (_, _, additional) = make_payload(call, 0, Hash::default(), &version).deconstruct();
+    assert_eq!((additional.0, additional.1), (version.spec_version, version.transaction_version));
+    assert_ne!((additional.0, additional.1), (runtime::VERSION.spec_version, runtime::VERSION.transaction_version));
+  }
+
+  fn v11_modules(metadata: &[u8]) -> Vec<ModuleMetadata> {
+    match RuntimeMetadataPrefixed::decode(&mut &metadata[..]).unwrap().1 {
+      RuntimeMetadata::V11(metadata) => decoded(&metadata.modules).unwrap().clone(),
+      _ => unreachable!(),
+    }
+  }
+
+  /// V12 metadata with the modules of V11, each module with calls gets the index `index`
+  fn to_v12(metadata: &[u8], index: impl Fn(u8) -> u8) -> Vec<u8> {
+    let modules = v11_modules(metadata);
+    let mut encoded = META_RESERVED.encode();
+    encoded.push(12);
+    codec::Compact(modules.len() as u32).encode_to(&mut encoded);
+    let mut position = 0;
+    for module in modules {
+      module.encode_to(&mut encoded);
+      encoded.push(index(position));
+      if module.calls.is_some() {
+        position += 1;
+      }
+    }
+    encoded
+  }
+
+  #[test]
+  fn test_call_signature() {
+    let metadata = Runtime::metadata().encode();
+    let transfer = call_signature(&metadata, "Balances", "transfer").unwrap().unwrap();
+    let call = Call::Balances(BalancesCall::transfer(AccountId::default(), 1)).encode();
+    assert_eq!((transfer.module_index, transfer.call_index), (call[0], call[1]));
+    assert_eq!(transfer.arguments.len(), 2);
+    assert!(call_signature(&metadata, "Balances", "unknown").unwrap().is_none());
+    assert!(check_call_encoding(&metadata, "Balances", "transfer").is_none());
+
+    // the index of the module is read from V12 metadata
+    let v12 = to_v12(&metadata, |position| position);
+    assert_eq!(call_signature(&v12, "Balances", "transfer").unwrap(), Some(transfer.clone()));
+    assert!(check_call_encoding(&v12, "Balances", "transfer").is_none());
+    let shifted = to_v12(&metadata, |position| position + 1);
+    let signature = call_signature(&shifted, "Balances", "transfer").unwrap().unwrap();
+    assert_eq!(signature.module_index, transfer.module_index + 1);
+    assert!(check_call_encoding(&shifted, "Balances", "transfer").unwrap().contains("please upgrade"));
+
+    // other versions are not supported
+    let mut v10 = metadata.clone();
+    v10[META_RESERVED.encoded_size()] = 10;
+    assert!(call_signature(&v10, "Balances", "transfer").is_err());
+    assert!(check_call_encoding(&v10, "Balances", "transfer").unwrap().contains("version 10 is not supported"));
+  }
+
+  #[test]
+  fn test_call_encoding_mismatch() {
+    let metadata = Runtime::metadata().encode();
+    let check = |modules: Vec<ModuleMetadata>| {
+      let mut node = match RuntimeMetadataPrefixed::decode(&mut &metadata[..]).unwrap() {
+        RuntimeMetadataPrefixed(reserved, RuntimeMetadata::V11(node)) => (reserved, node),
+        _ => unreachable!(),
+      };
+      node.1.modules = DecodeDifferent::Decoded(modules);
+      check_call_encoding(&RuntimeMetadataPrefixed(node.0, RuntimeMetadata::V11(node.1)).encode(), "Balances", "transfer")
+    };
+    let balances = |modules: &mut Vec<ModuleMetadata>| -> Vec<FunctionMetadata> {
+      let module = modules.iter_mut().find(|m| decoded(&m.name).unwrap() == "Balances").unwrap();
+      match module.calls.as_mut().unwrap() {
+        DecodeDifferent::Decoded(calls) => std::mem::take(calls),
+        _ => unreachable!(),
+      }
+    };
+    let with_calls = |modules: &mut Vec<ModuleMetadata>, calls: Vec<FunctionMetadata>| {
+      let module = modules.iter_mut().find(|m| decoded(&m.name).unwrap() == "Balances").unwrap();
+      module.calls = Some(DecodeDifferent::Decoded(calls));
+    };
+
+    // `transfer` is moved to another call index
+    let mut modules = v11_modules(&metadata);
+    let mut calls = balances(&mut modules);
+    calls.swap(0, 1);
+    with_calls(&mut modules, calls);
+    assert!(check(modules).unwrap().contains("please upgrade"));
+
+    // the amount is of another type
+    let mut modules = v11_modules(&metadata);
+    let mut calls = balances(&mut modules);
+    let transfer = calls.iter_mut().find(|call| decoded(&call.name).unwrap() == "transfer").unwrap();
+    match &mut transfer.arguments {
+      DecodeDifferent::Decoded(arguments) => arguments[1].ty = DecodeDifferent::Decoded("Compact<u64>".to_string()),
+      _ => unreachable!(),
+    }
+    with_calls(&mut modules, calls);
+    assert!(check(modules).unwrap().contains("Compact<u64>"));
+
+    // `transfer` is removed
+    let mut modules = v11_modules(&metadata);
+    let calls = balances(&mut modules).into_iter().filter(|call| decoded(&call.name).unwrap() != "transfer").collect();
+    with_calls(&mut modules, calls);
+    assert!(check(modules).unwrap().contains("not found"));
+  }
+}
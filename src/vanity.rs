@@ -0,0 +1,172 @@
+use std::sync::atomic::{ AtomicBool, AtomicU64, Ordering };
+use std::sync::{ mpsc, Arc };
+use std::thread;
+use std::time::{ Duration, Instant };
+use indicatif::{ ProgressBar, ProgressStyle };
+use rand::Rng;
+use zeroize::Zeroizing;
+
+use crate::crypto::{ Crypto, AccountId32, Ss58AddressFormat, Ss58Codec };
+use crate::wallet::Address;
+use crate::error::Result;
+
+const BASE58_ALPHABET: &'static str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Case-insensitive patterns of vanity address, empty pattern matches any address
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pattern {
+  prefix: String,
+  suffix: String,
+  contains: String,
+}
+
+impl Pattern {
+  /// `format` is the address format of the network, the prefix is checked against it
+  pub fn new(prefix: Option<&str>, suffix: Option<&str>, contains: Option<&str>, format: Ss58AddressFormat) -> Result<Self> {
+    let pattern = Self {
+      prefix: prefix.unwrap_or("").to_lowercase(),
+      suffix: suffix.unwrap_or("").to_lowercase(),
+      contains: contains.unwrap_or("").to_lowercase(),
+    };
+    if pattern.prefix.is_empty() && pattern.suffix.is_empty() && pattern.contains.is_empty() {
+      return Err("At least one of prefix, suffix and contains is required".into())
+    }
+    // `0` is the only character which is not in base58 alphabet in either case
+    for s in vec![&pattern.prefix, &pattern.suffix, &pattern.contains] {
+      if let Some(c) = s.chars().find(|c| !c.is_ascii_alphanumeric() || *c == '0') {
+        return Err(format!("`{}` is not a base58 character", c).into())
+      }
+    }
+    // otherwise no address matches and the workers never stop
+    let chars = first_chars(format);
+    if let Some(c) = pattern.prefix.chars().next() {
+      if !chars.iter().any(|first| first.to_ascii_lowercase() == c) {
+        let chars: String = chars.into_iter().collect();
+        return Err(format!("Addresses of the network start with one of `{}`, no address starts with `{}`", chars, pattern.prefix).into())
+      }
+    }
+    Ok(pattern)
+  }
+
+  pub fn is_match(&self, addr: &str) -> bool {
+    let addr = addr.to_lowercase();
+    addr.starts_with(&self.prefix) && addr.ends_with(&self.suffix) && addr.contains(&self.contains)
+  }
+}
+
+/// Characters an address of `format` can start with
+pub fn first_chars(format: Ss58AddressFormat) -> Vec<char> {
+  // the first character is decided by the format and the highest bytes of the public key
+  let min = AccountId32::from([0u8; 32]).to_ss58check_with_version(format);
+  let max = AccountId32::from([0xffu8; 32]).to_ss58check_with_version(format);
+  // a leading zero byte is always encoded as `1`
+  if max.starts_with('1') {
+    return vec!['1']
+  }
+  let alphabet: Vec<char> = BASE58_ALPHABET.chars().collect();
+  let index = |addr: &str| BASE58_ALPHABET.find(&addr[..1]).unwrap();
+  let (start, end) = (index(&min), index(&max));
+  let mut chars = if min.len() == max.len() {
+    alphabet[start..=end].to_vec()
+  } else {
+    // addresses get one character longer in the range, the first character starts over from `2`
+    alphabet[start..].iter().chain(alphabet[1..=end].iter()).cloned().collect()
+  };
+  chars.sort();
+  chars.dedup();
+  chars
+}
+
+/// Generate keypairs on `threads` threads until the address matches `pattern`
+pub fn generate<T: Crypto + 'static>(pattern: Pattern, threads: usize) -> Result<Address> {
+  let found = Arc::new(AtomicBool::new(false));
+  let attempts = Arc::new(AtomicU64::new(0));
+  let pattern = Arc::new(pattern);
+  let (tx, rx) = mpsc::channel();
+
+  let workers: Vec<_> = (0..threads.max(1)).map(|_| {
+    let (found, attempts, pattern, tx) = (found.clone(), attempts.clone(), pattern.clone(), tx.clone());
+    thread::spawn(move || {
+      let mut rng = rand::thread_rng();
+      let mut seed = Zeroizing::new([0u8; 32]);
+      while !found.load(Ordering::Relaxed) {
+        rng.fill(&mut seed[..]);
+        attempts.fetch_add(1, Ordering::Relaxed);
+        if let Ok(address) = Address::from_seed_with_path::<T>(&seed[..], "") {
+          if pattern.is_match(&address.addr) {
+            found.store(true, Ordering::Relaxed);
+            let _ = tx.send(address);
+          }
+        }
+      }
+    })
+  }).collect();
+  drop(tx);
+
+  let bar = ProgressBar::new_spinner();
+  bar.set_style(ProgressStyle::default_spinner()
+                  .template("{spinner:.green} [{elapsed_precise}] {msg}"));
+  let start = Instant::now();
+  let result = loop {
+    match rx.recv_timeout(Duration::from_millis(200)) {
+      Ok(address) => break Ok(address),
+      Err(mpsc::RecvTimeoutError::Timeout) => {
+        let count = attempts.load(Ordering::Relaxed);
+        let rate = count as f64 / start.elapsed().as_secs_f64().max(0.001);
+        bar.set_message(&format!("{} attempts, {:.0} attempts/s", count, rate));
+        bar.tick();
+      },
+      Err(mpsc::RecvTimeoutError::Disconnected) => break Err("Vanity address workers exited unexpectedly".into()),
+    }
+  };
+  bar.finish_and_clear();
+
+  found.store(true, Ordering::Relaxed);
+  for worker in workers {
+    let _ = worker.join();
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crypto::*;
+
+  #[test]
+  fn test_pattern() {
+    let polkadot = Ss58AddressFormat::PolkadotAccount;
+    let addr = "15FarxkDPL7LPvBPd1RDMGugGFs8be2ijuHEuLJd9z67PdNm";
+    assert!(Pattern::new(Some("15far"), None, None, polkadot).unwrap().is_match(addr));
+    assert!(Pattern::new(None, Some("PDNM"), Some("dpl7"), polkadot).unwrap().is_match(addr));
+    assert!(!Pattern::new(Some("16"), None, None, polkadot).unwrap().is_match(addr));
+    assert!(!Pattern::new(None, None, Some("xyz"), polkadot).unwrap().is_match(addr));
+
+    assert!(Pattern::new(None, None, None, polkadot).is_err());
+    assert!(Pattern::new(Some("10"), None, None, polkadot).is_err());
+    assert!(Pattern::new(None, Some("a-b"), None, polkadot).is_err());
+    // polkadot addresses always start with `1`
+    assert!(Pattern::new(Some("2"), None, None, polkadot).is_err());
+    assert!(Pattern::new(None, Some("2"), None, polkadot).is_ok());
+  }
+
+  #[test]
+  fn test_first_chars() {
+    assert_eq!(first_chars(Ss58AddressFormat::PolkadotAccount), vec!['1']);
+    let kusama = first_chars(Ss58AddressFormat::KusamaAccount);
+    assert!(kusama.contains(&'H') && !kusama.contains(&'1'));
+    let substrate = first_chars(Ss58AddressFormat::SubstrateAccount);
+    assert!(substrate.contains(&'5') && !substrate.contains(&'1'));
+    assert!(Pattern::new(Some("h"), None, None, Ss58AddressFormat::KusamaAccount).is_ok());
+  }
+
+  #[test]
+  fn test_generate() {
+    set_default_ss58_version(Ss58AddressFormat::PolkadotAccount);
+    let pattern = Pattern::new(None, None, Some("a"), Ss58AddressFormat::PolkadotAccount).unwrap();
+    let address = generate::<Sr25519>(pattern.clone(), 2).unwrap();
+    assert!(pattern.is_match(&address.addr));
+    assert_eq!(address.crypto_type, "sr25519");
+    assert_eq!(address.addr, Sr25519::to_address(&address.into_pair::<Sr25519>()));
+  }
+}
@@ -1,18 +1,27 @@
 use serde::{Serialize, Deserialize};
-use rustbreak::{FileDatabase};
-use rustbreak::deser::Bincode;
 use serde_json::json;
 
 use std::path::PathBuf;
 use std::time::SystemTime;
-use std::fs;
+use std::fmt;
+use std::cell::RefCell;
+use rand::{thread_rng, Rng};
+use sodalite::SecretboxKey;
+use zeroize::{ Zeroize, Zeroizing };
 
 use crate::keystore::{Keystore, Encoding};
 use crate::crypto::*;
 use crate::pkcs8;
-use crate::networks::Network;
+use crate::networks::{ Networks, DEFAULT_NETWORK };
+use crate::error;
+use crate::migration::{ Header, Schema };
+use crate::backend::{ self, Backend };
 
-const DEFAULT_WALLET_NAME: &'static str = "polkadot";
+pub const DEFAULT_WALLET_NAME: &'static str = "polkadot";
+/// Directory of wallets other than the default one
+const WALLETS_DIR: &'static str = "wallets";
+/// Number of automatic backups kept for every wallet
+const WALLET_BACKUPS: usize = 5;
 
 
 #[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
@@ -23,6 +32,17 @@ pub struct Address {
   pub seed: Vec<u8>,
   pub network: String,
   pub created_at: u64,
+  /// Mnemonic phrase encrypted by password, empty if the phrase is not kept
+  pub phrase: Vec<u8>,
+  /// Derivation path from `seed`, e.g. `//polkadot//0/1`, empty for root key
+  pub path: String,
+  /// Archived address is hidden from `listaddresses` and `getbalances`, but it can still be read by label
+  pub archived: bool,
+  /// Milliseconds of the last backup which is read back and verified, 0 if it is never backed up
+  pub backed_up_at: u64,
+  /// The seed is still encrypted by the master key because the wallet is locked, it is never saved
+  #[serde(skip)]
+  pub locked: bool,
 }
 
 impl Address {
@@ -30,7 +50,7 @@ impl Address {
     if self.seed.len() == 0 {
       println!("{:<15} {:<55} {:<7}", self.label, self.addr, "*");
     } else {
-      println!("{:<15} {:<55} {:<7}", self.label, self.addr, self.crypto_type);
+      println!("{:<15} {:<55} {:<7} {}", self.label, self.addr, self.crypto_type, self.path);
     }
   }
 
@@ -38,17 +58,39 @@ impl Address {
     self.seed.len() == 0
   } 
 
-  pub fn into_keystore(&self, password: Option<String>) -> Keystore {
+  /// Replace the seed, the old one is wiped
+  pub fn set_seed(&mut self, seed: Vec<u8>) {
+    self.seed.zeroize();
+    self.seed = seed;
+  }
+
+  /// Wipe the seed and encrypted phrase, it is called on drop
+  pub fn wipe(&mut self) {
+    self.seed.zeroize();
+    self.phrase.zeroize();
+  }
+
+  /// Keystore version 3, the secretbox key is derived from password by scrypt
+  pub fn into_keystore(&self, password: Option<&str>, networks: &Networks) -> Keystore {
+    let encrypted = password.map_or(false, |v| !v.is_empty());
+    let encoding = Encoding::v3(&self.crypto_type, encrypted);
+    self.to_keystore(encoding, password, networks)
+  }
+
+  /// Keystore version 2, it is only kept for the wallets which do not support version 3
+  pub fn into_legacy_keystore(&self, password: Option<&str>, networks: &Networks) -> Keystore {
+    let encoding = Encoding::v2(&self.crypto_type);
+    self.to_keystore(encoding, password, networks)
+  }
+
+  /// `networks` are the built-in and configured networks, the genesis hash of the address's network is kept in meta
+  fn to_keystore(&self, encoding: Encoding, password: Option<&str>, networks: &Networks) -> Keystore {
     let mut keystore = Keystore {
       address: self.addr.clone(),
       encoded: "".to_string(),
-      encoding: Encoding {
-        content: vec!["pkcs8".to_owned()],
-        r#type: "xsalsa20-poly1305".to_owned(),
-        version: "2".to_owned(),
-      },
+      encoding: encoding,
       meta: json!({
-        "genesisHash": Network::default().genesis_hash(),
+        "genesisHash": networks.get(&self.network).map(|network| network.genesis_hash.clone()).unwrap_or_default(),
         "name": self.label,
         "tags": [],
         "whenCreated": self.created_at,
@@ -57,32 +99,37 @@ impl Address {
 
     let (public_key, secret_key) = match self.crypto_type.as_str() {
       "sr25519" => {
-        let pair = Sr25519::pair_from_secret_slice(&self.seed[..]).unwrap();
-        (pair.public().to_raw_vec(), pair.to_raw_vec())
+        let pair = self.into_pair::<Sr25519>();
+        (pair.public().to_raw_vec(), Zeroizing::new(pair.to_raw_vec()))
       },
       "ed25519" => {
-        let pair = Ed25519::pair_from_secret_slice(&self.seed[..]).unwrap();
-        (pair.public().to_raw_vec(), pair.to_raw_vec())
+        let pair = self.into_pair::<Ed25519>();
+        (pair.public().to_raw_vec(), Zeroizing::new(pair.to_raw_vec()))
       },
       "ecdsa" => {
-        let pair = Ecdsa::pair_from_secret_slice(&self.seed[..]).unwrap();
-        (pair.public().to_raw_vec(), pair.to_raw_vec())
+        let pair = self.into_pair::<Ecdsa>();
+        (pair.public().to_raw_vec(), Zeroizing::new(pair.to_raw_vec()))
       }
       _ => unreachable!()
     };
 
-    let encoded = pkcs8::encode(&secret_key[..], &public_key[..], password).unwrap();
-    keystore.encoded = format!("0x{}", hex::encode(encoded));
-    keystore.encoding.content.push(self.crypto_type.clone());
+    keystore.encoded = if keystore.encoding.version == "3" {
+      let encoded = pkcs8::encode_scrypt(&secret_key[..], &public_key[..], password).unwrap();
+      base64::encode(encoded)
+    } else {
+      let encoded = pkcs8::encode(&secret_key[..], &public_key[..], password).unwrap();
+      format!("0x{}", hex::encode(encoded))
+    };
     keystore
   }
 
-  pub fn from_keystore(keystore: Keystore, password: Option<String>) -> Result<Self, ()> {
+  /// The network is looked up in `networks` by the genesis hash in meta, it is empty if the network is unknown
+  pub fn from_keystore(keystore: Keystore, password: Option<&str>, networks: &Networks) -> Result<Self, ()> {
     let mut address = Self::default();
     address.label = keystore.label();
     address.created_at = keystore.when_created();
     address.crypto_type = keystore.crypto().clone();
-    address.network = Network::from_genesis_hash(&keystore.genesis_hash()).into();
+    address.network = networks.from_genesis_hash(&keystore.genesis_hash()).map(|network| network.name.clone()).unwrap_or_default();
 
     match keystore.crypto().as_str() {
       "ecdsa" => {
@@ -118,42 +165,118 @@ impl Address {
   }
 
   pub fn generate<T: Crypto>() -> Self {
-    let (pair, _, seed) = T::Pair::generate_with_phrase(None);
-    let seed_slice: &[u8] = seed.as_ref();
+    Self::generate_with_phrase::<T>().0
+  }
+
+  pub fn generate_with_phrase<T: Crypto>() -> (Self, Zeroizing<String>) {
+    Self::generate_with_path::<T>(&DerivationPath::default()).unwrap()
+  }
+
+  pub fn generate_with_path<T: Crypto>(path: &DerivationPath) -> Result<(Self, Zeroizing<String>), ()> {
+    let (_, phrase, _) = T::Pair::generate_with_phrase(None);
+    let phrase = Zeroizing::new(phrase);
+    let address = Self::from_phrase_with_path::<T>(&phrase, path)?;
+    Ok((address, phrase))
+  }
+
+  pub fn from_phrase<T: Crypto>(phrase: &str) -> Result<Self, ()> {
+    Self::from_phrase_with_path::<T>(phrase, &DerivationPath::default())
+  }
+
+  /// The seed is generated from phrase and password of `path`, the junctions are kept in `Address.path`
+  pub fn from_phrase_with_path<T: Crypto>(phrase: &str, path: &DerivationPath) -> Result<Self, ()> {
+    let password = path.password.as_ref().map(|v| v.as_str());
+    match T::Pair::from_phrase(phrase, password) {
+      Ok((_, mut seed)) => {
+        let address = Self::from_seed_with_path::<T>(seed.as_ref(), &path.path);
+        seed.as_mut().zeroize();
+        address
+      },
+      Err(_) => return Err(()),
+    }
+  }
+
+  /// `path` is hard and soft junctions only, e.g. `//polkadot//0/1`
+  pub fn from_seed_with_path<T: Crypto>(seed: &[u8], path: &str) -> Result<Self, ()> {
+    let pair = T::pair_from_secret_slice_with_path(seed, path)?;
     let addr = T::to_address(&pair);
     let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64;
-    Address {
+    let address = Address {
       label: String::default(),
       addr: addr,
       crypto_type: T::crypto_type().to_owned(),
-      network: Network::default().into(),
-      seed: seed_slice.to_vec(),
+      network: DEFAULT_NETWORK.to_string(),
+      seed: seed.to_vec(),
       created_at: now,
-    }
+      phrase: vec![],
+      path: path.to_string(),
+      archived: false,
+      backed_up_at: 0,
+      locked: false,
+    };
+    Ok(address)
   }
 
-  pub fn from_phrase<T: Crypto>(phrase: &str) -> Result<Self, ()> {
-    match T::Pair::from_phrase(phrase, None) {
-      Ok((pair, seed)) => {
-        let seed_slice: &[u8] = seed.as_ref();
-        let addr = T::to_address(&pair);
-        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64;
-        let address = Address {
-          label: String::default(),
-          addr: addr,
-          crypto_type: T::crypto_type().to_owned(),
-          network: Network::default().into(),
-          seed: seed_slice.to_vec(),
-          created_at: now,
-        };
-        Ok(address)
-      },
-      Err(_) => return Err(()),
+  /// Derive a new address from the same seed, `path` is appended to the path of current address
+  pub fn derive(&self, path: &str) -> Result<Self, ()> {
+    if self.is_watchonly() {
+      return Err(())
     }
+    let mut address = self.clone();
+    address.path = format!("{}{}", self.path, path);
+    address.addr = match self.crypto_type.as_str() {
+      "sr25519" => Sr25519::to_address(&address.try_into_pair::<Sr25519>()?),
+      "ed25519" => Ed25519::to_address(&address.try_into_pair::<Ed25519>()?),
+      "ecdsa" => Ecdsa::to_address(&address.try_into_pair::<Ecdsa>()?),
+      _ => return Err(()),
+    };
+    address.label = String::default();
+    address.created_at = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64;
+    // the child is a new address, it is neither archived nor backed up
+    address.archived = false;
+    address.backed_up_at = 0;
+    Ok(address)
   }
 
   pub fn into_pair<T: Crypto>(&self) -> <T as Crypto>::Pair {
-    T::pair_from_secret_slice(&self.seed[..]).unwrap()
+    assert!(!self.locked, "The seed of {} is encrypted, unlock the wallet first", self.addr);
+    self.try_into_pair::<T>().unwrap()
+  }
+
+  /// Fails if the seed is still encrypted
+  pub fn try_into_pair<T: Crypto>(&self) -> Result<<T as Crypto>::Pair, ()> {
+    if self.locked {
+      return Err(())
+    }
+    T::pair_from_secret_slice_with_path(&self.seed[..], &self.path)
+  }
+
+  pub fn has_phrase(&self) -> bool {
+    !self.phrase.is_empty()
+  }
+
+  /// Keep the mnemonic phrase encrypted by password
+  pub fn encrypt_phrase(&mut self, phrase: &str, password: &str) -> Result<(), ()> {
+    if password.is_empty() {
+      return Err(())
+    }
+    self.phrase = pkcs8::encrypt_scrypt(phrase.as_bytes(), password)?;
+    Ok(())
+  }
+
+  pub fn decrypt_phrase(&self, password: &str) -> Result<Zeroizing<String>, ()> {
+    if !self.has_phrase() {
+      return Err(())
+    }
+    let decrypted = pkcs8::decrypt_scrypt(&self.phrase[..], password)?;
+    let phrase = String::from_utf8(decrypted.to_vec()).map_err(|_| () )?;
+    Ok(Zeroizing::new(phrase))
+  }
+}
+
+impl Drop for Address {
+  fn drop(&mut self) {
+    self.wipe();
   }
 }
 
@@ -167,6 +290,8 @@ mod address_tests {
   use super::Address;
   use crate::keystore::Keystore;
   use crate::crypto::*;
+  use crate::networks::{ Network, Networks };
+  use zeroize::Zeroize;
 
   fn setup() {
     set_default_ss58_version(Ss58AddressFormat::PolkadotAccount);
@@ -184,12 +309,98 @@ mod address_tests {
     assert_ne!(ec_address.seed, Vec::<u8>::new());
   }
 
+  #[test]
+  fn test_from_phrase() {
+    setup();
+    let phrase = "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+    let address = Address::from_phrase::<Sr25519>(phrase).unwrap();
+    let pair = sr25519::Pair::from_seed_slice(&address.seed[..]).unwrap();
+    assert_eq!(pair.public().0, hex!("46ebddef8cd9bb167dc30878d7113b7e168e6f0646beffd77d69d39bad76b47a"));
+    assert_eq!(address.addr, "12bzRJfh7arnnfPPUZHeJUaE62QLEwhK48QnH9LXeK2m1iZU");
+    assert_eq!(address.crypto_type, "sr25519");
+
+    assert!(Address::from_phrase::<Sr25519>("bottom drive obey lake").is_err());
+  }
+
+  #[test]
+  fn test_from_phrase_with_path() {
+    setup();
+    let phrase = "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+    let path = DerivationPath::parse("//Alice").unwrap();
+    let address = Address::from_phrase_with_path::<Sr25519>(phrase, &path).unwrap();
+    let expect = sr25519::Pair::from_string(&format!("{}//Alice", phrase), None).unwrap();
+    assert_eq!(address.addr, expect.public().to_ss58check());
+    assert_eq!(address.path, "//Alice");
+
+    let path = DerivationPath::parse("//Alice///secret").unwrap();
+    let address = Address::from_phrase_with_path::<Sr25519>(phrase, &path).unwrap();
+    let expect = sr25519::Pair::from_string(&format!("{}//Alice///secret", phrase), None).unwrap();
+    assert_eq!(address.addr, expect.public().to_ss58check());
+    assert_eq!(address.path, "//Alice");
+  }
+
+  #[test]
+  fn test_derive() {
+    setup();
+    let phrase = "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+    let root = Address::from_phrase::<Sr25519>(phrase).unwrap();
+    let parent = root.derive("//polkadot").unwrap();
+    let child = parent.derive("//0/1").unwrap();
+    let expect = sr25519::Pair::from_string(&format!("{}//polkadot//0/1", phrase), None).unwrap();
+    assert_eq!(child.path, "//polkadot//0/1");
+    assert_eq!(child.seed, root.seed);
+    assert_eq!(child.addr, expect.public().to_ss58check());
+    assert_eq!(child.into_pair::<Sr25519>().public(), expect.public());
+
+    let ed_root = Address::from_phrase::<Ed25519>(phrase).unwrap();
+    assert!(ed_root.derive("/soft").is_err());
+
+    let mut archived = root.clone();
+    archived.archived = true;
+    archived.backed_up_at = 1591600236132;
+    let child = archived.derive("//0").unwrap();
+    assert!(!child.archived);
+    assert_eq!(child.backed_up_at, 0);
+  }
+
+  #[test]
+  fn test_encrypt_phrase() {
+    setup();
+    let (mut address, phrase) = Address::generate_with_phrase::<Sr25519>();
+    assert!(!address.has_phrase());
+    assert!(address.encrypt_phrase(&phrase, "").is_err());
+
+    address.encrypt_phrase(&phrase, "111111").unwrap();
+    assert!(address.has_phrase());
+    assert!(address.decrypt_phrase("incorrect").is_err());
+    assert_eq!(address.decrypt_phrase("111111").unwrap(), phrase);
+
+    let restored = Address::from_phrase::<Sr25519>(&phrase).unwrap();
+    assert_eq!(restored.addr, address.addr);
+  }
+
+  #[test]
+  fn test_wipe() {
+    setup();
+    let (mut address, phrase) = Address::generate_with_phrase::<Sr25519>();
+    address.encrypt_phrase(&phrase, "111111").unwrap();
+    for buf in vec![&mut address.seed, &mut address.phrase] {
+      assert!(buf.iter().any(|b| *b != 0));
+      buf[..].zeroize();
+      assert!(buf.iter().all(|b| *b == 0));
+    }
+
+    address.wipe();
+    assert!(address.seed.is_empty());
+    assert!(!address.has_phrase());
+  }
+
   #[test]
   fn test_from_keystore_with_incorrect_password() {
     setup();
     let keystore = Keystore::parse_from_file("tests/fixtures/ecdsa.json".into()).unwrap();
-    let password = Some("incorrect".to_owned()); // 111111 is correct password
-    match Address::from_keystore(keystore, password) {
+    let password = Some("incorrect"); // 111111 is correct password
+    match Address::from_keystore(keystore, password, &Networks::default()) {
       Ok(_) => unreachable!(),
       Err(e) => assert_eq!(e, ()),
     }
@@ -201,7 +412,7 @@ mod address_tests {
     setup();
 
     let keystore = Keystore::parse_from_file("tests/fixtures/ecdsa.json".into()).unwrap();
-    let password = Some("111111".to_string());
+    let password = Some("111111");
 
     let seed = hex!("bda7ce4ab5c0bdcfbf3f5353adb1ae795aa793261dd478c26cb97735b68bc687");
     let pair = ecdsa::Pair::from_seed(&seed);
@@ -213,9 +424,14 @@ mod address_tests {
       seed: pair.to_raw_vec(),
       network: "polkadot".to_owned(),
       created_at: 1591600236132u64,
+      phrase: vec![],
+      path: String::default(),
+      archived: false,
+      backed_up_at: 0,
+      locked: false,
     };
 
-    let address = Address::from_keystore(keystore, password).unwrap();
+    let address = Address::from_keystore(keystore, password, &Networks::default()).unwrap();
 
     assert_eq!(address, expect_address);
   }
@@ -225,7 +441,7 @@ mod address_tests {
     setup();
 
     let keystore = Keystore::parse_from_file("tests/fixtures/sr25519.json".into()).unwrap();
-    let password = Some("111111".to_string());
+    let password = Some("111111");
 
     let seed = hex!("bda7ce4ab5c0bdcfbf3f5353adb1ae795aa793261dd478c26cb97735b68bc687");
     let pair = sr25519::Pair::from_seed(&seed);
@@ -237,8 +453,13 @@ mod address_tests {
       seed: pair.to_raw_vec(),
       network: "polkadot".to_owned(),
       created_at: 1591600865993u64,
+      phrase: vec![],
+      path: String::default(),
+      archived: false,
+      backed_up_at: 0,
+      locked: false,
     };
-    let address = Address::from_keystore(keystore, password).unwrap();
+    let address = Address::from_keystore(keystore, password, &Networks::default()).unwrap();
     assert_eq!(address, expect_address);
   }
 
@@ -247,7 +468,7 @@ mod address_tests {
     setup();
 
     let keystore = Keystore::parse_from_file("tests/fixtures/ed25519.json".into()).unwrap();
-    let password = Some("111111".to_string());
+    let password = Some("111111");
 
     let seed = hex!("bda7ce4ab5c0bdcfbf3f5353adb1ae795aa793261dd478c26cb97735b68bc687");
     let pair = ed25519::Pair::from_seed(&seed);
@@ -259,8 +480,13 @@ mod address_tests {
       seed: pair.to_raw_vec(),
       network: "polkadot".to_owned(),
       created_at: 1591600763959u64,
+      phrase: vec![],
+      path: String::default(),
+      archived: false,
+      backed_up_at: 0,
+      locked: false,
     };
-    let address = Address::from_keystore(keystore, password).unwrap();
+    let address = Address::from_keystore(keystore, password, &Networks::default()).unwrap();
     assert_eq!(address, expect_address);
   }
 
@@ -278,11 +504,16 @@ mod address_tests {
       seed: pair.to_raw_vec(),
       network: "polkadot".to_owned(),
       created_at: 1591600236132u64,
+      phrase: vec![],
+      path: String::default(),
+      archived: false,
+      backed_up_at: 0,
+      locked: false,
     };
-    let password = Some("111111".to_owned());
-    let keystore = address.into_keystore(password.clone());
+    let password = Some("111111");
+    let keystore = address.into_keystore(password, &Networks::default());
 
-    let decoded_address = Address::from_keystore(keystore, password).unwrap();
+    let decoded_address = Address::from_keystore(keystore, password, &Networks::default()).unwrap();
     assert_eq!(address, decoded_address);
   }
 
@@ -301,11 +532,16 @@ mod address_tests {
       seed: pair.to_raw_vec(),
       network: "polkadot".to_owned(),
       created_at: 1591600236132u64,
+      phrase: vec![],
+      path: String::default(),
+      archived: false,
+      backed_up_at: 0,
+      locked: false,
     };
-    let password = Some("111111".to_owned());
-    let keystore = address.into_keystore(password.clone());
+    let password = Some("111111");
+    let keystore = address.into_keystore(password, &Networks::default());
 
-    let decoded_address = Address::from_keystore(keystore, password).unwrap();
+    let decoded_address = Address::from_keystore(keystore, password, &Networks::default()).unwrap();
     assert_eq!(address, decoded_address);
   }
 
@@ -323,102 +559,732 @@ mod address_tests {
       seed: pair.to_raw_vec(),
       network: "polkadot".to_owned(),
       created_at: 1591600236132u64,
+      phrase: vec![],
+      path: String::default(),
+      archived: false,
+      backed_up_at: 0,
+      locked: false,
+    };
+    let password = Some("111111");
+    let keystore = address.into_keystore(password, &Networks::default());
+
+    let decoded_address = Address::from_keystore(keystore, password, &Networks::default()).unwrap();
+    assert_eq!(address, decoded_address);
+  }
+
+  #[test]
+  fn test_keystore_of_custom_network() {
+    setup();
+    let local = Network {
+      name: "local".to_owned(),
+      genesis_hash: format!("0x{}", "ab".repeat(32)),
+      ..Network::default()
+    };
+    let networks = Networks::with(&[local.clone()]);
+
+    let mut address = Address::generate::<Sr25519>();
+    address.network = local.name.clone();
+    let password = Some("111111");
+    let keystore = address.into_keystore(password, &networks);
+    assert_eq!(keystore.genesis_hash(), local.genesis_hash);
+
+    let restored = Address::from_keystore(keystore.clone(), password, &networks).unwrap();
+    assert_eq!(restored.network, "local");
+    // the network is unknown without config
+    let restored = Address::from_keystore(keystore, password, &Networks::default()).unwrap();
+    assert_eq!(restored.network, "");
+  }
+
+  #[test]
+  fn test_into_legacy_keystore_for_sr25519() {
+    setup();
+
+    let seed = hex!("bda7ce4ab5c0bdcfbf3f5353adb1ae795aa793261dd478c26cb97735b68bc687");
+    let pair = sr25519::Pair::from_seed(&seed);
+
+    let address = Address {
+      addr: pair.public().to_ss58check(),
+      label: "sr25519".to_owned(),
+      crypto_type: "sr25519".to_owned(),
+      seed: pair.to_raw_vec(),
+      network: "polkadot".to_owned(),
+      created_at: 1591600236132u64,
+      phrase: vec![],
+      path: String::default(),
+      archived: false,
+      backed_up_at: 0,
+      locked: false,
     };
-    let password = Some("111111".to_owned());
-    let keystore = address.into_keystore(password.clone());
+    let password = Some("111111");
+    let keystore = address.into_legacy_keystore(password, &Networks::default());
+    assert_eq!(keystore.encoding.version, "2");
 
-    let decoded_address = Address::from_keystore(keystore, password).unwrap();
+    let decoded_address = Address::from_keystore(keystore, password, &Networks::default()).unwrap();
     assert_eq!(address, decoded_address);
   }
+
+  #[test]
+  fn test_from_keystore_v3_for_sr25519() {
+    setup();
+
+    let keystore = Keystore::parse_from_file("tests/fixtures/sr25519_v3.json".into()).unwrap();
+    let password = Some("111111");
+    let address = Address::from_keystore(keystore, password, &Networks::default()).unwrap();
+    assert_eq!(address.addr, "14cwHq7pwagFBTdT9E3TTzh2WsuugSAoxL53fpywct2KVSQG");
+    assert_eq!(address.label, "sr25519");
+  }
 }
 
 
 
 #[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct Wallet {
+  /// It must be the first field, see `migration`
+  pub header: Header,
   pub name: String,
   pub address_book: Vec<Address>,
+  /// Random master key encrypted by the wallet passphrase, empty if the wallet is not encrypted.
+  /// Seeds of addresses are encrypted by the master key.
+  pub master_key: Vec<u8>,
 }
 
 impl Wallet {
   pub fn new(name: String) -> Self {
     Self {
+      header: Header::new(<Self as Schema>::VERSION),
       name: name,
       address_book: vec![],
+      master_key: vec![],
+    }
+  }
+
+  pub fn is_encrypted(&self) -> bool {
+    !self.master_key.is_empty()
+  }
+
+  /// The address must not exist, the label must pass `check_label`
+  pub fn add(&mut self, address: Address) -> error::Result<()> {
+    if self.address_book.iter().any(|a| a.addr == address.addr) {
+      return Err(format!("`{}` already exists", address.addr).into())
     }
+    self.check_label(&address.label, &address.addr)?;
+    self.address_book.push(address);
+    Ok(())
   }
 
-  pub fn add(&mut self, address: Address) {
-    let addr = address.clone().addr;
-    if self.get(addr.as_str()).is_none() {
-      self.address_book.push(address);
+  /// Label must not be used by other addresses, and it must not look like an address
+  pub fn check_label(&self, label: &str, addr: &str) -> error::Result<()> {
+    if label.is_empty() {
+      return Ok(())
+    }
+    if AccountId32::from_ss58check_with_version(label).is_ok() {
+      return Err(format!("Label `{}` can not be an address", label).into())
+    }
+    match self.address_book.iter().find(|a| a.label == label && a.addr != addr) {
+      Some(a) => Err(format!("Label `{}` is used by `{}`", label, a.addr).into()),
+      None => Ok(()),
     }
   }
 
-  #[allow(dead_code)]
-  pub fn delete(&mut self, label: &str) {
-    self.address_book.retain(|address| address.label.as_str() != label );
+  /// Returns false if the address does not exist
+  pub fn delete(&mut self, label_or_addr: &str) -> bool {
+    match self.position(label_or_addr) {
+      Some(index) => {
+        self.address_book.remove(index);
+        true
+      },
+      None => false,
+    }
   }
 
   pub fn get(&self, label_or_addr: &str) -> Option<&Address>{
-    for address in &self.address_book {
-      if address.label.as_str() == label_or_addr || address.addr.as_str() == label_or_addr {
-        return Some(&address)
+    let index = self.position(label_or_addr)?;
+    self.address_book.get(index)
+  }
+
+  pub fn get_mut(&mut self, label_or_addr: &str) -> Option<&mut Address> {
+    let index = self.position(label_or_addr)?;
+    self.address_book.get_mut(index)
+  }
+
+  /// Address is matched before label, a label used by more than one address matches nothing
+  fn position(&self, label_or_addr: &str) -> Option<usize> {
+    if let Some(index) = self.address_book.iter().position(|a| a.addr == label_or_addr) {
+      return Some(index)
+    }
+    let mut indexes = self.address_book.iter().enumerate()
+      .filter(|(_, a)| !a.label.is_empty() && a.label == label_or_addr)
+      .map(|(index, _)| index);
+    match (indexes.next(), indexes.next()) {
+      (Some(index), None) => Some(index),
+      _ => None,
+    }
+  }
+
+  /// Labels shared by addresses and labels which look like addresses
+  pub fn conflicts(&self) -> Vec<Conflict> {
+    let mut conflicts = vec![];
+    let mut labels: Vec<&str> = self.address_book.iter().map(|a| a.label.as_str()).filter(|l| !l.is_empty()).collect();
+    labels.sort();
+    labels.dedup();
+    for label in labels {
+      let addrs: Vec<String> = self.address_book.iter().filter(|a| a.label == label).map(|a| a.addr.clone()).collect();
+      if addrs.len() > 1 {
+        conflicts.push(Conflict::DuplicateLabel(label.to_string(), addrs));
       }
     }
-    return None;
+    for address in self.address_book.iter() {
+      if !address.label.is_empty() && AccountId32::from_ss58check_with_version(&address.label).is_ok() {
+        conflicts.push(Conflict::AddressLabel(address.label.clone(), address.addr.clone()));
+      }
+    }
+    conflicts
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Conflict {
+  /// Label and the addresses using it
+  DuplicateLabel(String, Vec<String>),
+  /// Label which looks like an address, and the address using it
+  AddressLabel(String, String),
+}
+
+impl fmt::Display for Conflict {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Conflict::DuplicateLabel(label, addrs) => write!(f, "Label `{}` is used by {} addresses: {}", label, addrs.len(), addrs.join(", ")),
+      Conflict::AddressLabel(label, addr) => write!(f, "Label `{}` of `{}` looks like an address", label, addr),
+    }
   }
 }
 
-pub struct WalletStore(FileDatabase<Wallet, Bincode>);
+pub struct WalletStore {
+  db: Box<dyn Backend<Wallet>>,
+  path: PathBuf,
+  name: String,
+  /// Decrypted master key, it is set by `unlock` and wiped by `lock`
+  master_key: RefCell<Option<Zeroizing<SecretboxKey>>>,
+}
 
 impl WalletStore {
+  /// Open the default wallet
   pub fn init(path: Option<&str>) -> Self {
-    let file = path.map(|v| {
-      let mut file = PathBuf::from(v);
-      file.push(DEFAULT_WALLET_NAME);
-      file
-    }).unwrap_or_else(|| {
-      let mut file = dirs::home_dir().unwrap();
-      file.push(".subwallet");
-      file.push(DEFAULT_WALLET_NAME);
-      file
-    });
+    Self::open(path, DEFAULT_WALLET_NAME)
+  }
+
+  /// Open the wallet `name`, it is created if it does not exist
+  pub fn open(path: Option<&str>, name: &str) -> Self {
+    let path = Self::data_path(path);
+    let backend = Wallet::new(name.to_owned());
+    let db = backend::open(&path, &Self::wallet_key(name), backend, WALLET_BACKUPS).expect("Failed to initialize wallet database.");
+    Self {
+      db: db,
+      path: path,
+      name: name.to_owned(),
+      master_key: RefCell::new(None),
+    }
+  }
+
+  /// Create and save an empty wallet `name`
+  pub fn create(path: Option<&str>, name: &str) -> error::Result<Self> {
+    if !Self::is_valid_name(name) {
+      return Err("Wallet name can only contain letters, numbers, `-` and `_`".into())
+    }
+    if Self::exists(path, name) {
+      return Err(format!("Wallet `{}` already exists", name).into())
+    }
+    let store = Self::open(path, name);
+    store.db.update(&mut |_| ()).expect("Failed to save");
+    Ok(store)
+  }
+
+  pub fn exists(path: Option<&str>, name: &str) -> bool {
+    backend::exists(&Self::data_path(path), &Self::wallet_key(name))
+  }
+
+  /// Names of all wallets, the default wallet is always included
+  pub fn list(path: Option<&str>) -> Vec<String> {
+    let mut names = vec![DEFAULT_WALLET_NAME.to_owned()];
+    let others = backend::list(&Self::data_path(path), WALLETS_DIR);
+    names.extend(others.into_iter().filter(|name| Self::is_valid_name(name)));
+    names
+  }
+
+  pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+  }
 
-    if !file.exists() {
-      fs::create_dir_all(file.parent().unwrap()).expect("Failed to create wallet file");
+  fn data_path(path: Option<&str>) -> PathBuf {
+    path.map(PathBuf::from).unwrap_or_else(|| {
+      let mut path = dirs::home_dir().unwrap();
+      path.push(".subwallet");
+      path
+    })
+  }
+
+  /// The default wallet is kept in data path for compatibility, others are in `wallets` directory
+  fn wallet_key(name: &str) -> String {
+    if name == DEFAULT_WALLET_NAME {
+      name.to_owned()
+    } else {
+      format!("{}/{}", WALLETS_DIR, name)
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// The data directory of wallets
+  pub fn db_path(&self) -> &PathBuf {
+    &self.path
+  }
+
+  pub fn is_encrypted(&self) -> bool {
+    self.db.read().expect("Failed to read data").is_encrypted()
+  }
+
+  pub fn is_locked(&self) -> bool {
+    self.master_key.borrow().is_none()
+  }
+
+  /// Decrypt master key by passphrase, then seeds are decrypted by `read` and encrypted by `save`
+  pub fn unlock(&self, passphrase: &str) -> error::Result<()> {
+    let encrypted = self.db.read().expect("Failed to read data").master_key.clone();
+    if encrypted.is_empty() {
+      return Err("Wallet is not encrypted".into())
     }
-    let backend = Wallet::new(DEFAULT_WALLET_NAME.to_owned());
-    let db = FileDatabase::<Wallet, Bincode>::load_from_path_or(file, backend).expect("Failed to initialize file database.");
-    Self(db)
+    let decrypted = pkcs8::decrypt_scrypt(&encrypted[..], passphrase).map_err(|_| "Incorrect wallet passphrase")?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&decrypted[..]);
+    *self.master_key.borrow_mut() = Some(key);
+    Ok(())
+  }
+
+  pub fn master_key(&self) -> Option<Zeroizing<SecretboxKey>> {
+    self.master_key.borrow().clone()
   }
 
-  pub fn load(&self) {
-    let _ = self.0.load();
+  /// Unlock wallet by the decrypted master key, e.g. the key is held by unlock agent
+  pub fn set_master_key(&self, key: Zeroizing<SecretboxKey>) {
+    *self.master_key.borrow_mut() = Some(key);
   }
 
-  pub fn save(&self, address: Address) {
-    self.load();
-    self.0.write(|backend| {
+  /// Wipe the decrypted master key
+  pub fn lock(&self) {
+    *self.master_key.borrow_mut() = None;
+  }
+
+  /// Encrypt seed by the master key
+  pub fn seal(&self, seed: &[u8]) -> error::Result<Vec<u8>> {
+    let key = self.master_key.borrow().clone().ok_or("Wallet is locked")?;
+    Self::seal_seed(seed, &key)
+  }
+
+  /// Encrypt seeds of all addresses by a random master key, the master key is encrypted by passphrase
+  pub fn encrypt(&self, passphrase: &str) -> error::Result<()> {
+    if passphrase.is_empty() {
+      return Err("Passphrase can not be empty".into())
+    }
+    if self.is_encrypted() {
+      return Err("Wallet is already encrypted".into())
+    }
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    thread_rng().fill(&mut key[..]);
+    let encrypted_key = pkcs8::encrypt_scrypt(&key[..], passphrase).map_err(|_| "Failed to encrypt master key")?;
+
+    // the seeds are sealed under the lock of the wallet, addresses saved by other processes are not lost.
+    // Backups and migration copies hold the seeds in plaintext, they are deleted under the same lock
+    self.db.write_purging_history(|wallet| -> error::Result<()> {
+      if wallet.is_encrypted() {
+        return Err("Wallet is already encrypted".into())
+      }
+      let sealed = wallet.address_book.iter()
+        .map(|address| Self::seal_seed(&address.seed, &key))
+        .collect::<error::Result<Vec<_>>>()?;
+      for (address, seed) in wallet.address_book.iter_mut().zip(sealed) {
+        address.set_seed(seed);
+      }
+      wallet.master_key = encrypted_key;
+      Ok(())
+    })??;
+
+    *self.master_key.borrow_mut() = Some(key);
+    Ok(())
+  }
+
+  /// Encrypt master key by new passphrase, seeds are not changed
+  pub fn change_passphrase(&self, old: &str, new: &str) -> error::Result<()> {
+    if new.is_empty() {
+      return Err("Passphrase can not be empty".into())
+    }
+    self.unlock(old)?;
+    let key = self.master_key.borrow().clone().unwrap();
+    let encrypted_key = pkcs8::encrypt_scrypt(&key[..], new).map_err(|_| "Failed to encrypt master key")?;
+    self.db.write(|backend| {
+      backend.master_key = encrypted_key
+    })?;
+    Ok(())
+  }
+
+  pub fn save(&self, address: Address) -> error::Result<()> {
+    let mut address = address;
+    self.db.write(|backend| {
+      if backend.is_encrypted() && !address.is_watchonly() {
+        let key = self.master_key.borrow().clone().ok_or("Wallet is locked")?;
+        let sealed = Self::seal_seed(&address.seed, &key)?;
+        address.set_seed(sealed);
+      }
+      backend.add(address)
+    })??;
+    Ok(())
+  }
+
+  /// Save address whose seed is already encrypted by the master key
+  pub fn save_sealed(&self, address: Address) -> error::Result<()> {
+    self.db.write(|backend| {
       backend.add(address)
-    }).expect("Failed to write address");
-    self.0.save().expect("Failed to save");
+    })??;
+    Ok(())
   }
 
+  /// Seeds are decrypted if the wallet is unlocked
   pub fn read(&self, label: &str) -> Option<Address> {
-    self.load();
-    let backend = self.0.borrow_data().expect("Failed to read data");
+    let backend = self.db.read().expect("Failed to read data");
     let v = backend.get(label);
     match v {
-      Some(addr) => Some(addr.clone()),
+      Some(addr) => Some(self.open_seed(addr.clone(), backend.is_encrypted())),
       None => None
     }
   }
 
   pub fn read_all(&self) -> Vec<Address> {
-    self.load();
-    let backend = self.0.borrow_data().expect("Failed to read data");
-    backend.address_book.clone()
+    let backend = self.db.read().expect("Failed to read data");
+    backend.address_book.iter().map(|address| {
+      self.open_seed(address.clone(), backend.is_encrypted())
+    }).collect()
+  }
+
+  /// Check the label of a new address before it is generated
+  pub fn check_label(&self, label: &str) -> error::Result<()> {
+    self.db.read().expect("Failed to read data").check_label(label, "")
+  }
+
+  /// Labels shared by addresses and labels which look like addresses
+  pub fn conflicts(&self) -> Vec<Conflict> {
+    self.db.read().expect("Failed to read data").conflicts()
+  }
+
+  pub fn set_label(&self, label_or_addr: &str, label: &str) -> error::Result<()> {
+    self.db.write(|backend| -> error::Result<()> {
+      let addr = backend.get(label_or_addr).map(|address| address.addr.clone())
+        .ok_or(format!("`{}` related address does not exist", label_or_addr))?;
+      backend.check_label(label, &addr)?;
+      if let Some(address) = backend.get_mut(&addr) {
+        address.label = label.to_string();
+      }
+      Ok(())
+    })?
+  }
+
+  pub fn set_archived(&self, label_or_addr: &str, archived: bool) -> error::Result<()> {
+    self.update(label_or_addr, |address| address.archived = archived)
+  }
+
+  /// Record a backup which is read back and verified
+  pub fn set_backed_up(&self, label_or_addr: &str) -> error::Result<()> {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64;
+    self.update(label_or_addr, |address| address.backed_up_at = now)
+  }
+
+  pub fn remove(&self, label_or_addr: &str) -> error::Result<()> {
+    let deleted = self.db.write(|backend| backend.delete(label_or_addr))?;
+    if !deleted {
+      return Err(format!("`{}` related address does not exist", label_or_addr).into())
+    }
+    Ok(())
+  }
+
+  /// Update the stored address in place, the seed is kept encrypted
+  fn update<F: FnOnce(&mut Address)>(&self, label_or_addr: &str, f: F) -> error::Result<()> {
+    let found = self.db.write(|backend| {
+      match backend.get_mut(label_or_addr) {
+        Some(address) => {
+          f(address);
+          true
+        },
+        None => false,
+      }
+    })?;
+    if !found {
+      return Err(format!("`{}` related address does not exist", label_or_addr).into())
+    }
+    Ok(())
+  }
+
+  fn seal_seed(seed: &[u8], key: &SecretboxKey) -> error::Result<Vec<u8>> {
+    if seed.is_empty() {
+      return Ok(vec![])
+    }
+    pkcs8::seal(seed, key).map_err(|_| "Failed to encrypt seed".into())
+  }
+
+  /// The address is marked `locked` if its seed can not be decrypted
+  fn open_seed(&self, mut address: Address, encrypted: bool) -> Address {
+    if !encrypted || address.seed.is_empty() {
+      return address
+    }
+    let opened = self.master_key.borrow().as_ref().and_then(|key| pkcs8::open(&address.seed[..], key).ok());
+    match opened {
+      Some(seed) => address.set_seed(seed.to_vec()),
+      None => address.locked = true,
+    }
+    address
   }
 }
 
+impl Drop for WalletStore {
+  fn drop(&mut self) {
+    self.lock();
+  }
+}
+
+#[cfg(test)]
+mod wallet_store_tests {
+  use super::*;
+  use std::fs;
+  use sp_core::crypto::{Ss58AddressFormat, set_default_ss58_version};
+
+  fn setup(name: &str) -> WalletStore {
+    set_default_ss58_version(Ss58AddressFormat::PolkadotAccount);
+    let path = std::env::temp_dir().join(format!("subwallet-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    WalletStore::init(path.to_str())
+  }
+
+  #[test]
+  fn test_encrypt_wallet() {
+    let store = setup("encrypt");
+    let address = Address::generate::<Sr25519>();
+    store.save(address.clone()).unwrap();
+    assert!(!store.is_encrypted());
+
+    store.encrypt("111111").unwrap();
+    assert!(store.is_encrypted());
+    assert!(store.encrypt("111111").is_err());
+    assert_eq!(store.read(&address.addr).unwrap(), address);
+
+    let store = WalletStore::init(store.db_path().to_str());
+    assert!(store.is_locked());
+    let locked = store.read(&address.addr).unwrap();
+    assert_ne!(locked.seed, address.seed);
+    assert!(locked.locked && !locked.is_watchonly());
+    assert!(locked.try_into_pair::<Sr25519>().is_err());
+    assert!(locked.derive("//0").is_err());
+    assert!(store.unlock("incorrect").is_err());
+    store.unlock("111111").unwrap();
+    assert_eq!(store.read(&address.addr).unwrap(), address);
+  }
+
+  #[test]
+  fn test_save_to_locked_wallet() {
+    let store = setup("locked");
+    store.encrypt("111111").unwrap();
+
+    let store = WalletStore::init(store.db_path().to_str());
+    assert!(store.save(Address::generate::<Sr25519>()).is_err());
+
+    let mut watchonly = Address::default();
+    watchonly.addr = "13EAmr66rhEEXD8mq5wiqvJ2aHibBYebF1ys9JRi1qyVjQyk".to_string();
+    assert!(store.save(watchonly).is_ok());
+  }
+
+  #[test]
+  fn test_change_passphrase() {
+    let store = setup("passphrase");
+    let address = Address::generate::<Ed25519>();
+    store.save(address.clone()).unwrap();
+    store.encrypt("111111").unwrap();
+    assert!(store.change_passphrase("incorrect", "222222").is_err());
+    store.change_passphrase("111111", "222222").unwrap();
+
+    let store = WalletStore::init(store.db_path().to_str());
+    assert!(store.unlock("111111").is_err());
+    store.unlock("222222").unwrap();
+    assert_eq!(store.read(&address.addr).unwrap(), address);
+  }
+
+  #[test]
+  fn test_named_wallets() {
+    let store = setup("named");
+    let path = store.db_path().to_str();
+    let address = Address::generate::<Sr25519>();
+    store.save(address.clone()).unwrap();
+
+    assert!(!WalletStore::exists(path, "treasury"));
+    let treasury = WalletStore::create(path, "treasury").unwrap();
+    assert_eq!(treasury.name(), "treasury");
+    assert!(WalletStore::exists(path, "treasury"));
+    assert!(WalletStore::create(path, "treasury").is_err());
+    assert!(WalletStore::create(path, "../treasury").is_err());
+    assert_eq!(WalletStore::list(path), vec![DEFAULT_WALLET_NAME.to_string(), "treasury".to_string()]);
+
+    assert!(treasury.read(&address.addr).is_none());
+    let treasury = WalletStore::open(path, "treasury");
+    treasury.save(Address::generate::<Ed25519>()).unwrap();
+    assert_eq!(treasury.read_all().len(), 1);
+    assert_eq!(WalletStore::init(path).read_all(), vec![address]);
+  }
+
+  fn files(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    fs::read_dir(dir).unwrap().flat_map(|entry| {
+      let path = entry.unwrap().path();
+      if path.is_dir() { files(&path) } else { vec![path] }
+    }).collect()
+  }
+
+  #[test]
+  fn test_no_plaintext_seed_after_encrypt() {
+    for backend in &["file", "sqlite"] {
+      set_default_ss58_version(Ss58AddressFormat::PolkadotAccount);
+      let path = std::env::temp_dir().join(format!("subwallet-purge-{}-{}", backend, std::process::id()));
+      let _ = fs::remove_dir_all(&path);
+      fs::create_dir_all(&path).unwrap();
+      fs::write(path.join("config.toml"), format!("[rpc]\n\n[storage]\nbackend = \"{}\"\n", backend)).unwrap();
+      // the migration of a v0 wallet leaves a copy, and every save leaves a backup
+      fs::copy("tests/fixtures/wallet_v0.bin", path.join(DEFAULT_WALLET_NAME)).unwrap();
+      let store = WalletStore::init(path.to_str());
+      for _ in 0..3 {
+        store.save(Address::generate::<Sr25519>()).unwrap();
+      }
+      let seeds: Vec<Vec<u8>> = store.read_all().into_iter()
+        .filter(|address| !address.is_watchonly())
+        .map(|address| address.seed.clone())
+        .collect();
+      assert_eq!(seeds.len(), 5);
+
+      store.encrypt("passphrase").unwrap();
+      for file in files(&path) {
+        let data = fs::read(&file).unwrap();
+        for seed in &seeds {
+          assert!(!data.windows(seed.len()).any(|window| window == &seed[..]), "{} holds a seed", file.display());
+        }
+      }
+      store.save(Address::generate::<Sr25519>()).unwrap();
+      assert_eq!(store.read_all().len(), 6);
+    }
+  }
+
+  #[test]
+  fn test_sqlite_backend() {
+    set_default_ss58_version(Ss58AddressFormat::PolkadotAccount);
+    let path = std::env::temp_dir().join(format!("subwallet-sqlite-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    fs::write(path.join("config.toml"), "[rpc]\n\n[storage]\nbackend = \"sqlite\"\n").unwrap();
+    let store = WalletStore::init(path.to_str());
+
+    let address = Address::generate::<Sr25519>();
+    store.save(address.clone()).unwrap();
+    store.encrypt("passphrase").unwrap();
+    store.lock();
+    assert_ne!(store.read(&address.addr).unwrap().seed, address.seed);
+    store.unlock("passphrase").unwrap();
+    assert_eq!(store.read(&address.addr).unwrap(), address);
+
+    WalletStore::create(path.to_str(), "treasury").unwrap();
+    assert_eq!(WalletStore::list(path.to_str()), vec![DEFAULT_WALLET_NAME.to_string(), "treasury".to_string()]);
+    assert!(!path.join(DEFAULT_WALLET_NAME).exists());
+  }
+
+  #[test]
+  fn test_address_lifecycle() {
+    let store = setup("lifecycle");
+    let mut address = Address::generate::<Sr25519>();
+    address.label = "ops".to_string();
+    store.save(address.clone()).unwrap();
+    let mut other = Address::generate::<Sr25519>();
+    other.label = "fee".to_string();
+    store.save(other.clone()).unwrap();
+
+    store.set_label("ops", "treasury").unwrap();
+    assert!(store.read("ops").is_none());
+    assert_eq!(store.read("treasury").unwrap().addr, address.addr);
+    assert!(store.set_label("treasury", "fee").is_err());
+    assert!(store.set_label("unknown", "new").is_err());
+
+    store.set_archived("treasury", true).unwrap();
+    assert!(store.read(&address.addr).unwrap().archived);
+
+    assert_eq!(store.read("treasury").unwrap().backed_up_at, 0);
+    store.set_backed_up("treasury").unwrap();
+    assert!(store.read("treasury").unwrap().backed_up_at > 0);
+
+    store.remove("treasury").unwrap();
+    assert!(store.read(&address.addr).is_none());
+    assert!(store.remove("treasury").is_err());
+    assert_eq!(store.read_all(), vec![other]);
+  }
+
+  #[test]
+  fn test_unique_labels() {
+    let store = setup("labels");
+    let mut address = Address::generate::<Sr25519>();
+    address.label = "demo".to_string();
+    store.save(address.clone()).unwrap();
+    // saving the same address again is refused, the stored one is kept
+    let err = store.save(address.clone()).unwrap_err();
+    assert_eq!(err.to_string(), format!("Other error: `{}` already exists", address.addr));
+
+    let mut other = Address::generate::<Ed25519>();
+    other.label = "demo".to_string();
+    assert!(store.check_label("demo").is_err());
+    assert!(store.save(other.clone()).is_err());
+
+    other.label = "13EAmr66rhEEXD8mq5wiqvJ2aHibBYebF1ys9JRi1qyVjQyk".to_string();
+    assert!(store.check_label(&other.label).is_err());
+    assert!(store.save(other.clone()).is_err());
+    other.label = "demo2".to_string();
+    store.save(other.clone()).unwrap();
+
+    assert!(store.set_label("demo2", "demo").is_err());
+    assert!(store.set_label("demo2", &address.addr).is_err());
+    assert_eq!(store.read_all().len(), 2);
+    assert!(store.conflicts().is_empty());
+  }
+
+  #[test]
+  fn test_conflicts() {
+    setup("conflicts");
+    let mut wallet = Wallet::new("conflicts".to_string());
+    let mut first = Address::generate::<Sr25519>();
+    first.label = "demo".to_string();
+    let mut second = Address::generate::<Sr25519>();
+    second.label = "demo".to_string();
+    let mut third = Address::generate::<Sr25519>();
+    third.label = first.addr.clone();
+    wallet.address_book = vec![first.clone(), second.clone(), third.clone()];
+
+    // ambiguous label matches nothing, address always matches
+    assert!(wallet.get("demo").is_none());
+    assert_eq!(wallet.get(&first.addr).unwrap().addr, first.addr);
+    assert_eq!(wallet.conflicts(), vec![
+      Conflict::DuplicateLabel("demo".to_string(), vec![first.addr.clone(), second.addr.clone()]),
+      Conflict::AddressLabel(first.addr.clone(), third.addr.clone()),
+    ]);
+  }
+
+  #[test]
+  fn test_lock() {
+    let store = setup("lock");
+    store.encrypt("111111").unwrap();
+    assert!(store.master_key().is_some());
+
+    store.lock();
+    assert!(store.is_locked());
+    assert!(store.master_key().is_none());
+    assert!(store.seal(&[1u8; 32]).is_err());
+  }
+}